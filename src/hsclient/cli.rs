@@ -3,17 +3,27 @@ use clap::{Arg, ArgAction, Command};
 use std::collections::HashMap;
 use std::env;
 use std::os::fd::AsFd;
+use std::path::PathBuf;
 
-use crate::hsclient::client::{ensure_server, get_exit_code, initialize, take_interpreter};
+use crate::hsclient::client::{
+    ensure_server, get_exit_code, get_exit_code_remote, initialize, pool_depth, set_pool_size,
+    status, take_interpreter, take_interpreter_remote, upgrade,
+};
 use crate::hsclient::proxy::do_proxy;
 use crate::hsserver::server::restart;
+use crate::hsserver::supervisor::CommandSpec;
 
 use super::proxy::TerminalModeGuard;
 
 enum Args {
     Restart,
-    Init(String),
-    Run(RunMode),
+    Upgrade,
+    Init(String, CommandSpec),
+    PoolSize(usize),
+    PoolDepth,
+    Status,
+    RemoteServe(String),
+    Run(RunMode, Option<PathBuf>, Option<String>),
 }
 
 enum RunMode {
@@ -31,6 +41,12 @@ fn parse_args() -> Result<Args> {
                 .action(ArgAction::SetTrue)
                 .help("Kill existing server and start a new one"),
         )
+        .arg(
+            Arg::new("upgrade")
+                .long("upgrade")
+                .action(ArgAction::SetTrue)
+                .help("Re-exec the running server in place, keeping the warm pool alive"),
+        )
         .arg(
             Arg::new("initialize")
                 .short('i')
@@ -38,6 +54,43 @@ fn parse_args() -> Result<Args> {
                 .value_name("PRELUDE")
                 .help("Initialize with a prelude script"),
         )
+        .arg(
+            Arg::new("exe")
+                .long("exe")
+                .value_name("PATH")
+                .requires("initialize")
+                .help("Interpreter executable to spawn instead of python3"),
+        )
+        .arg(
+            Arg::new("arg")
+                .long("arg")
+                .value_name("ARG")
+                .action(ArgAction::Append)
+                .requires("initialize")
+                .help("Extra argv entry for the spawned interpreter (repeatable)"),
+        )
+        .arg(
+            Arg::new("env")
+                .long("env")
+                .value_name("KEY=VALUE")
+                .action(ArgAction::Append)
+                .requires("initialize")
+                .help("Environment variable to set for the spawned interpreter (repeatable)"),
+        )
+        .arg(
+            Arg::new("clear_env")
+                .long("clear-env")
+                .action(ArgAction::SetTrue)
+                .requires("initialize")
+                .help("Don't inherit the server's environment; use only --env entries"),
+        )
+        .arg(
+            Arg::new("spawn_cwd")
+                .long("cwd")
+                .value_name("PATH")
+                .requires("initialize")
+                .help("Working directory for the spawned interpreter"),
+        )
         .arg(
             Arg::new("code")
                 .short('c')
@@ -50,6 +103,42 @@ fn parse_args() -> Result<Args> {
                 .value_name("MODULE")
                 .help("Run library module as a script"),
         )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .value_name("PATH")
+                .help("Record the session to an asciinema v2 cast file"),
+        )
+        .arg(
+            Arg::new("remote")
+                .long("remote")
+                .value_name("HOST:PORT")
+                .help("Take the interpreter from a remote host running --remote-serve instead of the local pool (requires PY_HOTSTART_REMOTE_TOKEN)"),
+        )
+        .arg(
+            Arg::new("remote_serve")
+                .long("remote-serve")
+                .value_name("HOST:PORT")
+                .help("Run as a remote interpreter host instead of a client (requires PY_HOTSTART_REMOTE_TOKEN)"),
+        )
+        .arg(
+            Arg::new("pool_size")
+                .long("pool-size")
+                .value_name("N")
+                .help("Set the number of pre-forked interpreters kept warm in the pool"),
+        )
+        .arg(
+            Arg::new("pool_depth")
+                .long("pool-depth")
+                .action(ArgAction::SetTrue)
+                .help("Print the number of ready interpreters currently in the pool"),
+        )
+        .arg(
+            Arg::new("status")
+                .long("status")
+                .action(ArgAction::SetTrue)
+                .help("Print a JSON report of the server's pool, tracked children, and active prelude"),
+        )
         .arg(Arg::new("script").index(1).help("Script file to run"))
         .arg(
             Arg::new("script_args")
@@ -63,16 +152,51 @@ fn parse_args() -> Result<Args> {
         .after_help("Usage: py-hotstart [options] [-c cmd | -m module | script.py] [args]")
         .get_matches();
 
+    if let Some(addr) = matches.get_one::<String>("remote_serve") {
+        return Ok(Args::RemoteServe(addr.to_string()));
+    }
     if matches.get_one::<bool>("restart").copied().unwrap_or(false) {
         return Ok(Args::Restart);
     }
+    if matches.get_one::<bool>("upgrade").copied().unwrap_or(false) {
+        return Ok(Args::Upgrade);
+    }
     let prelude = matches
         .get_one::<String>("initialize")
         .map(|s| s.to_string());
     if let Some(code) = prelude {
-        return Ok(Args::Init(code));
+        let mut spec = CommandSpec::default();
+        if let Some(exe) = matches.get_one::<String>("exe") {
+            spec.executable = exe.to_string();
+        }
+        if let Some(args) = matches.get_many::<String>("arg") {
+            spec.args = args.cloned().collect();
+        }
+        if let Some(entries) = matches.get_many::<String>("env") {
+            for entry in entries {
+                let (key, value) = entry.split_once('=').with_context(|| {
+                    format!("invalid --env entry {:?}, expected KEY=VALUE", entry)
+                })?;
+                spec.env.insert(key.to_string(), value.to_string());
+            }
+        }
+        spec.clear_env = matches.get_one::<bool>("clear_env").copied().unwrap_or(false);
+        spec.cwd = matches.get_one::<String>("spawn_cwd").map(|s| s.to_string());
+        return Ok(Args::Init(code, spec));
+    }
+    if let Some(n) = matches.get_one::<String>("pool_size") {
+        let n = n.parse::<usize>().context("invalid --pool-size value")?;
+        return Ok(Args::PoolSize(n));
+    }
+    if matches.get_one::<bool>("pool_depth").copied().unwrap_or(false) {
+        return Ok(Args::PoolDepth);
+    }
+    if matches.get_one::<bool>("status").copied().unwrap_or(false) {
+        return Ok(Args::Status);
     }
 
+    let record_path = matches.get_one::<String>("record").map(PathBuf::from);
+    let remote_addr = matches.get_one::<String>("remote").map(|s| s.to_string());
     let code_mode = matches.get_one::<String>("code");
     let module_mode = matches.get_one::<String>("module");
     let script = matches.get_one::<String>("script");
@@ -95,7 +219,7 @@ fn parse_args() -> Result<Args> {
         RunMode::Repl
     };
 
-    Ok(Args::Run(run_mode))
+    Ok(Args::Run(run_mode, record_path, remote_addr))
 }
 
 fn generate_instructions(terminal_mode: &TerminalModeGuard, run_mode: RunMode) -> Result<String> {
@@ -131,28 +255,36 @@ fn generate_instructions(terminal_mode: &TerminalModeGuard, run_mode: RunMode) -
     };
     let argv_str = json::stringify(argv);
 
-    let mode = terminal_mode.get_original();
-    let cc_elems = &mode
-        .control_chars
-        .iter()
-        .map(|b| format!("b'\\x{:02x}'", b))
-        .collect::<Vec<_>>()
-        .join(", ");
-    let cc = format!("[{}]", cc_elems);
-    let iflag = mode.input_flags.bits();
-    let oflag = mode.output_flags.bits();
-    let cflag = mode.control_flags.bits();
-    let lflag = mode.local_flags.bits();
+    // Non-interactive clients (piped stdin/stdout) have no terminal settings to mirror onto
+    // the server-side PTY, so the termios import/tcsetattr call is only emitted for TTYs.
+    let termios_setup = match terminal_mode.get_original() {
+        Some(mode) => {
+            let cc_elems = &mode
+                .control_chars
+                .iter()
+                .map(|b| format!("b'\\x{:02x}'", b))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let cc = format!("[{}]", cc_elems);
+            let iflag = mode.input_flags.bits();
+            let oflag = mode.output_flags.bits();
+            let cflag = mode.control_flags.bits();
+            let lflag = mode.local_flags.bits();
+            format!(
+                "import termios\ntermios.tcsetattr(0, termios.TCSANOW, [{iflag}, {oflag}, {cflag}, {lflag}, 38400, 38400, {cc}])\n"
+            )
+        }
+        None => String::new(),
+    };
     let instructions = format!(
-        r#"import sys, os, termios
+        r#"import sys, os
 
 os.environ.clear()
 os.environ.update({env_str})
 os.chdir({cwd_str:?})
 sys.argv.clear()
 sys.argv.extend({argv_str})
-termios.tcsetattr(0, termios.TCSANOW, [{iflag}, {oflag}, {cflag}, {lflag}, 38400, 38400, {cc}])
-
+{termios_setup}
 {snippet}
 "#,
     );
@@ -160,18 +292,54 @@ termios.tcsetattr(0, termios.TCSANOW, [{iflag}, {oflag}, {cflag}, {lflag}, 38400
 }
 
 pub fn main() -> Result<i32> {
-    ensure_server()?;
     let args = parse_args()?;
+
+    // A remote-serve or remote-take run talks to a `hsserver::remote` host over TCP instead of
+    // the local Unix-socket daemon, so it has no need (and no way) to start/find that daemon.
+    if !matches!(args, Args::RemoteServe(_) | Args::Run(_, _, Some(_))) {
+        ensure_server()?;
+    }
+
     match args {
         Args::Restart => {
             restart()?;
             Ok(0)
         }
-        Args::Init(prelude_script) => {
-            initialize(&prelude_script)?;
+        Args::Upgrade => {
+            upgrade()?;
             Ok(0)
         }
-        Args::Run(run_mode) => {
+        Args::Init(prelude_script, spec) => {
+            initialize(&prelude_script, &spec)?;
+            Ok(0)
+        }
+        Args::PoolSize(n) => {
+            set_pool_size(n)?;
+            Ok(0)
+        }
+        Args::PoolDepth => {
+            println!("{}", pool_depth()?);
+            Ok(0)
+        }
+        Args::Status => {
+            println!("{}", json::stringify_pretty(status()?, 2));
+            Ok(0)
+        }
+        Args::RemoteServe(addr) => {
+            crate::hsserver::remote::serve_remote(&addr)?;
+            Ok(0)
+        }
+        Args::Run(run_mode, record_path, Some(remote_addr)) => {
+            let terminal_mode = TerminalModeGuard::new(std::io::stdin().as_fd())?;
+            let instructions = generate_instructions(&terminal_mode, run_mode)?;
+            let (stream, id) = take_interpreter_remote(&remote_addr, &instructions)?;
+
+            // Proxy the remote pty (relayed over `stream`) until it's done, then return exit code
+            do_proxy(&terminal_mode, stream, None, record_path.as_deref())?;
+            let exit_code = get_exit_code_remote(&remote_addr, &id)?;
+            Ok(exit_code)
+        }
+        Args::Run(run_mode, record_path, None) => {
             let mut interpreter = take_interpreter()?;
 
             let terminal_mode = TerminalModeGuard::new(std::io::stdin().as_fd())?;
@@ -182,6 +350,8 @@ pub fn main() -> Result<i32> {
             do_proxy(
                 &terminal_mode,
                 interpreter.take_pty_master().context("no pty")?,
+                None,
+                record_path.as_deref(),
             )?;
             let exit_code = get_exit_code(interpreter.id())?;
             Ok(exit_code)
@@ -1,17 +1,46 @@
+use crate::hsclient::proxy::sync_winsize;
+use crate::hsserver::remote::REMOTE_TOKEN_ENV;
 use crate::hsserver::server::{ensure, SOCKET_PATH};
+use crate::hsserver::supervisor::CommandSpec;
+use crate::remote::{read_json_frame, write_json_frame};
+use crate::sendfd::{read_frame, write_frame, PtyMaster};
 use anyhow::{bail, Context, Result};
-use nix::cmsg_space;
-use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
-use std::io::IoSliceMut;
-use std::io::{Read, Write};
-use std::os::fd::{FromRawFd, OwnedFd};
-use std::os::unix::io::{AsRawFd, RawFd};
+use nix::sys::socket::{send, shutdown, MsgFlags, Shutdown};
+use std::env;
+use std::net::TcpStream;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::io::RawFd;
 use std::os::unix::net::UnixStream;
 
 pub struct ClientInterpreter {
     pub id: String,
     pub control_fd: OwnedFd,
-    pub pty_master_fd: OwnedFd,
+    pty_master_fd: Option<PtyMaster>,
+}
+
+/// An interpreter spawned in "three stream" mode (see `take_interpreter_three_stream`): unlike
+/// `ClientInterpreter`, there's no `pty_master_fd` to proxy, since the caller already holds the
+/// stdin/stdout/stderr ends it handed the server.
+pub struct ThreeStreamInterpreter {
+    pub id: String,
+    pub control_fd: OwnedFd,
+}
+
+impl ClientInterpreter {
+    /// Take ownership of the interpreter's PTY master, leaving `None` behind. Its window size
+    /// has already been synced from the user's terminal at take time (see `take_interpreter`).
+    pub fn take_pty_master(&mut self) -> Option<PtyMaster> {
+        self.pty_master_fd.take()
+    }
+
+    /// Send the generated startup snippet over `control_fd` and shut it down, mirroring
+    /// `Interpreter::run_instructions` on the server side of the same control socket.
+    pub fn run_instructions(&mut self, instructions: &str) -> Result<()> {
+        send(self.control_fd.as_raw_fd(), instructions.as_bytes(), MsgFlags::empty())
+            .context("interpreter run_instructions send failed")?;
+        shutdown(self.control_fd.as_raw_fd(), Shutdown::Both).context("shutdown function failed")?;
+        Ok(())
+    }
 }
 
 pub fn ensure_server() -> Result<()> {
@@ -31,73 +60,181 @@ pub fn ensure_server() -> Result<()> {
     Ok(())
 }
 
+// Connect and send a single length-framed request, with no fds attached. Most requests are a
+// short text command with a plain "OK"/"ERROR: ..." response. TAKE also goes through here, since
+// only its response carries fds; TAKE3 doesn't, since its request carries the caller's stdio fds.
 fn send_request(req: &str) -> Result<UnixStream> {
     let mut stream = UnixStream::connect(SOCKET_PATH).context("Failed to connect to server")?;
-    stream.write_all(req.as_bytes())?;
-    stream.flush()?;
+    write_frame(&mut stream, req.as_bytes(), &[]).context("Failed to send request")?;
     Ok(stream)
 }
 
-pub fn initialize(prelude: &str) -> Result<()> {
-    let mut stream = send_request(&format!("INIT {}", prelude))?;
-    let mut buf = [0u8; 1024];
-    let n = stream.read(&mut buf)?;
-    let resp = String::from_utf8_lossy(&buf[..n]).to_string();
+// Read a single framed "OK"/"ERROR: ..." response with no fds expected.
+fn read_ok_response(stream: &mut UnixStream, op: &str) -> Result<()> {
+    let (payload, _fds) = read_frame(stream).with_context(|| format!("Failed to read {op} response"))?;
+    let resp = String::from_utf8_lossy(&payload).to_string();
     if resp.trim() == "OK" {
         Ok(())
     } else {
-        bail!("INIT failed: {}", resp)
+        bail!("{op} failed: {}", resp)
     }
 }
 
-pub fn take_interpreter() -> Result<ClientInterpreter> {
-    let stream = send_request("TAKE")?;
-    let mut buf = [0u8; 32];
-    let mut iov = [IoSliceMut::new(&mut buf)];
-    let mut cmsgspace = cmsg_space!([RawFd; 2]);
-
-    let (n, control_fd, pty_fd) = {
-        let msg = recvmsg::<()>(
-            stream.as_raw_fd(),
-            &mut iov,
-            Some(&mut cmsgspace),
-            MsgFlags::empty(),
-        )
-        .context("Failed to recvmsg")?;
-        if msg.bytes == 0 {
-            bail!("No message in response");
-        }
-        let mut control_fd: Option<OwnedFd> = None;
-        let mut pty_fd: Option<OwnedFd> = None;
-        for cmsg in msg.cmsgs()? {
-            if let ControlMessageOwned::ScmRights(fds) = cmsg {
-                let mut owned_fds: Vec<OwnedFd> = fds.into_iter().map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }).collect();
-                control_fd = Some(owned_fds.remove(0));
-                pty_fd = Some(owned_fds.remove(0));
-            }
-        }
-        (msg.bytes, control_fd.context("No control_fd in response")?, pty_fd.context("No pty_fd in response")?)
+pub fn initialize(prelude: &str, spec: &CommandSpec) -> Result<()> {
+    let payload = json::object! {
+        prelude: prelude,
+        spec: spec.to_json(),
     };
+    let mut stream = send_request(&format!("INIT {}", json::stringify(payload)))?;
+    read_ok_response(&mut stream, "INIT")
+}
+
+pub fn take_interpreter() -> Result<ClientInterpreter> {
+    let mut stream = send_request("TAKE")?;
+    let (payload, mut fds) = read_frame(&mut stream).context("Failed to read TAKE response")?;
+    if fds.len() != 2 {
+        bail!("expected 2 fds in TAKE response, got {}", fds.len());
+    }
+    let pty_fd = unsafe { OwnedFd::from_raw_fd(fds.pop().unwrap()) };
+    let control_fd = unsafe { OwnedFd::from_raw_fd(fds.pop().unwrap()) };
 
-    let resp_str = String::from_utf8_lossy(&iov[0][..n]);
+    let resp_str = String::from_utf8_lossy(&payload);
     let id = resp_str
         .strip_prefix("OK ")
         .with_context(|| format!("invalid response {}", resp_str))?;
+
+    let pty_master_fd = PtyMaster::from(pty_fd);
+    // Apply the user's terminal size to the PTY before any code starts running on it; waiting
+    // until the proxy loop starts (after run_instructions) would let the interpreter see a
+    // stale size if it queries it as its first action.
+    if let Err(e) = sync_winsize(std::io::stdout().as_fd(), pty_master_fd.as_fd()) {
+        eprintln!("Failed to sync window size: {}", e);
+    }
+
     Ok(ClientInterpreter {
         id: id.to_string(),
         control_fd,
-        pty_master_fd: pty_fd,
+        pty_master_fd: Some(pty_master_fd),
     })
 }
 
+pub fn upgrade() -> Result<()> {
+    let mut stream = send_request("UPGRADE")?;
+    read_ok_response(&mut stream, "UPGRADE")
+}
+
+/// Spawn an interpreter wired directly to `stdin_fd`/`stdout_fd`/`stderr_fd` instead of a shared
+/// PTY, e.g. so a supervising process can read stderr independently of stdout (structured logs
+/// vs. program output). `pty` should be true only if those fds are themselves backed by a PTY,
+/// so the child knows whether to acquire it as its controlling terminal.
+pub fn take_interpreter_three_stream(
+    stdin_fd: RawFd,
+    stdout_fd: RawFd,
+    stderr_fd: RawFd,
+    pty: bool,
+) -> Result<ThreeStreamInterpreter> {
+    let mut stream = UnixStream::connect(SOCKET_PATH).context("Failed to connect to server")?;
+    let req = format!("TAKE3 {}", if pty { 1 } else { 0 });
+    write_frame(&mut stream, req.as_bytes(), &[stdin_fd, stdout_fd, stderr_fd])
+        .context("Failed to send TAKE3 request")?;
+
+    let (payload, mut fds) = read_frame(&mut stream).context("Failed to read TAKE3 response")?;
+    if fds.len() != 1 {
+        bail!("expected 1 fd in TAKE3 response, got {}", fds.len());
+    }
+    let control_fd = unsafe { OwnedFd::from_raw_fd(fds.pop().unwrap()) };
+
+    // Like TAKE, a successful TAKE3 response is the bare child id (no "OK " framing) alongside
+    // the control fd; a failed request errors out over the same connection instead.
+    let id = String::from_utf8_lossy(&payload).to_string();
+
+    Ok(ThreeStreamInterpreter { id, control_fd })
+}
+
+pub fn set_pool_size(n: usize) -> Result<()> {
+    let mut stream = send_request(&format!("POOL {}", n))?;
+    read_ok_response(&mut stream, "POOL")
+}
+
+pub fn pool_depth() -> Result<usize> {
+    let mut stream = send_request("POOLSIZE")?;
+    let (payload, _fds) = read_frame(&mut stream).context("Failed to read POOLSIZE response")?;
+    let resp = String::from_utf8_lossy(&payload).trim().to_string();
+    let depth = resp
+        .strip_prefix("OK ")
+        .with_context(|| format!("unexpected pool depth response {}", resp))?;
+    depth.parse::<usize>().context("Failed to parse pool depth from server")
+}
+
+/// Fetch the server's STATUS report (pool depth, per-child pid/uptime/exit code, and a
+/// length+hash summary of the active prelude) as the raw JSON object, for `--status` to print.
+pub fn status() -> Result<json::JsonValue> {
+    let mut stream = send_request("STATUS")?;
+    let (payload, _fds) = read_frame(&mut stream).context("Failed to read STATUS response")?;
+    let resp = String::from_utf8_lossy(&payload).to_string();
+    let body = resp
+        .trim()
+        .strip_prefix("OK ")
+        .with_context(|| format!("STATUS failed: {}", resp))?;
+    json::parse(body).context("invalid STATUS response json")
+}
+
 pub fn get_exit_code(id: &str) -> Result<i32> {
     let req = format!("EXITCODE {}", id);
     let mut stream = send_request(&req)?;
-    let mut buf = [0u8; 1024];
-    let n = stream.read(&mut buf)?;
-    let resp = String::from_utf8_lossy(&buf[..n]).trim().to_string();
-    let exit_code = resp.strip_prefix("OK ")
+    let (payload, _fds) = read_frame(&mut stream).context("Failed to read EXITCODE response")?;
+    let resp = String::from_utf8_lossy(&payload).trim().to_string();
+    let exit_code = resp
+        .strip_prefix("OK ")
         .with_context(|| format!("unexpected exit code response {}", resp))?;
-    exit_code.parse::<i32>()
-        .context("Failed to parse exit code from server")
+    exit_code.parse::<i32>().context("Failed to parse exit code from server")
+}
+
+// The remote host refuses to run without this set (see `hsserver::remote::REMOTE_TOKEN_ENV`),
+// so a client connecting to one must have it set too, to the same value.
+fn remote_token() -> Result<String> {
+    env::var(REMOTE_TOKEN_ENV).with_context(|| {
+        format!(
+            "set {} to the remote host's shared secret before using --remote",
+            REMOTE_TOKEN_ENV
+        )
+    })
+}
+
+/// Take an interpreter from a remote `hsserver::remote::serve_remote` host instead of the local
+/// pool. There's no fd to hand back over the network, so the returned `TcpStream` carries the
+/// remote PTY's raw bytes directly; pass it straight to `do_proxy`, which only needs
+/// `Read + Write + AsFd` and treats it exactly like a local `PtyMaster`. Returns the stream
+/// alongside the child id `get_exit_code_remote` needs for its own follow-up connection.
+pub fn take_interpreter_remote(addr: &str, instructions: &str) -> Result<(TcpStream, String)> {
+    let mut stream =
+        TcpStream::connect(addr).with_context(|| format!("Failed to connect to remote host {}", addr))?;
+    let req = json::object! {
+        cmd: "TAKE",
+        token: remote_token()?,
+        prelude: json::Null,
+        spec: CommandSpec::default().to_json(),
+        instructions: instructions,
+    };
+    write_json_frame(&mut stream, &req).context("Failed to send remote TAKE request")?;
+    let resp = read_json_frame(&mut stream).context("Failed to read remote TAKE response")?;
+    let id = resp["id"]
+        .as_str()
+        .with_context(|| format!("unexpected remote TAKE response {}", resp))?
+        .to_string();
+    Ok((stream, id))
+}
+
+/// Query a remote host for the exit code of an interpreter previously taken with
+/// `take_interpreter_remote`. A fresh connection per call, mirroring the local EXITCODE
+/// protocol: the remote `Supervisor` keeps its own exit-info bookkeeping across connections.
+pub fn get_exit_code_remote(addr: &str, id: &str) -> Result<i32> {
+    let mut stream =
+        TcpStream::connect(addr).with_context(|| format!("Failed to connect to remote host {}", addr))?;
+    let req = json::object! { cmd: "EXITCODE", token: remote_token()?, id: id };
+    write_json_frame(&mut stream, &req).context("Failed to send remote EXITCODE request")?;
+    let resp = read_json_frame(&mut stream).context("Failed to read remote EXITCODE response")?;
+    resp["exit_code"]
+        .as_i32()
+        .with_context(|| format!("unexpected remote EXITCODE response {}", resp))
 }
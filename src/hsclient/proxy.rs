@@ -3,23 +3,37 @@ use anyhow::{Context, Result};
 use nix::libc;
 use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
 use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use nix::unistd::isatty;
 use signal_hook::consts::SIGWINCH;
 use signal_hook::low_level::pipe;
+use signal_hook::SigId;
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
 use std::io::{Read, Stdin, Stdout, Write};
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
 use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 // Create wrappers for TIOCGWINSZ and TIOCSWINSZ
 nix::ioctl_read_bad!(tiocgwinsz, libc::TIOCGWINSZ, libc::winsize);
 nix::ioctl_write_ptr_bad!(tiocswinsz, libc::TIOCSWINSZ, libc::winsize);
 
+/// Puts `fd` into raw mode for the lifetime of the guard, restoring the original settings on
+/// drop. If `fd` isn't a real terminal (a pipe or file, e.g. `echo code | py-hotstart` or
+/// output redirected in CI) this is a no-op, since raw mode and restoration are meaningless
+/// there; callers should consult `is_tty()` to drive non-interactive behavior.
 pub struct TerminalModeGuard {
-    fd: BorrowedFd<'static>,
-    original: Termios,
+    restore: Option<(BorrowedFd<'static>, Termios)>,
 }
 
 impl TerminalModeGuard {
     pub fn new(fd: BorrowedFd<'_>) -> Result<TerminalModeGuard> {
+        if !isatty(fd.as_raw_fd()).unwrap_or(false) {
+            return Ok(TerminalModeGuard { restore: None });
+        }
+
         let termios = tcgetattr(fd).context("Failed to get terminal attributes")?;
         let original = termios.clone();
         let mut raw = termios;
@@ -28,24 +42,29 @@ impl TerminalModeGuard {
 
         let fd_static: BorrowedFd<'static> = unsafe { std::mem::transmute(fd) };
         Ok(TerminalModeGuard {
-            fd: fd_static,
-            original,
+            restore: Some((fd_static, original)),
         })
     }
 
-    pub fn get_original(&self) -> &Termios {
-        &self.original
+    pub fn is_tty(&self) -> bool {
+        self.restore.is_some()
+    }
+
+    pub fn get_original(&self) -> Option<&Termios> {
+        self.restore.as_ref().map(|(_, original)| original)
     }
 }
 
 impl Drop for TerminalModeGuard {
     fn drop(&mut self) {
-        let _ = tcsetattr(self.fd, SetArg::TCSANOW, &self.original);
+        if let Some((fd, original)) = &self.restore {
+            let _ = tcsetattr(*fd, SetArg::TCSANOW, original);
+        }
     }
 }
 
-/// Sync the terminal window size from `from_fd` to `to_fd`.
-fn sync_winsize(from_fd: BorrowedFd, to_fd: BorrowedFd) -> Result<()> {
+/// Read `fd`'s window size, falling back to a sane default if it isn't a terminal.
+fn get_winsize(fd: BorrowedFd) -> libc::winsize {
     let mut ws: libc::winsize = libc::winsize {
         ws_row: 0,
         ws_col: 0,
@@ -53,7 +72,7 @@ fn sync_winsize(from_fd: BorrowedFd, to_fd: BorrowedFd) -> Result<()> {
         ws_ypixel: 0,
     };
 
-    let res = unsafe { tiocgwinsz(from_fd.as_raw_fd(), &mut ws) };
+    let res = unsafe { tiocgwinsz(fd.as_raw_fd(), &mut ws) };
     if res.is_err() {
         eprintln!("Failed to get terminal size: {:?}", res);
         // If we can't get the terminal size, use a default.
@@ -64,14 +83,214 @@ fn sync_winsize(from_fd: BorrowedFd, to_fd: BorrowedFd) -> Result<()> {
             ws_ypixel: 480,
         };
     }
+    ws
+}
 
+/// Sync the terminal window size from `from_fd` to `to_fd`. Exposed crate-wide so
+/// `take_interpreter` can apply the user's terminal size to a freshly-handed-out PTY master
+/// before any code starts running on it, closing the race where the interpreter reads a stale
+/// size before `do_proxy`'s own sync would otherwise run.
+pub(crate) fn sync_winsize(from_fd: BorrowedFd, to_fd: BorrowedFd) -> Result<()> {
+    let ws = get_winsize(from_fd);
     unsafe { tiocswinsz(to_fd.as_raw_fd(), &ws) }.context("failed to set winsize")?;
-
     Ok(())
 }
 
+/// Tees a PTY session into an asciinema v2 cast file (JSON-lines: a header line followed by
+/// one `[seconds, "o"|"i", chunk]` event per read) so it can be replayed with standard
+/// asciinema tooling. Every event is flushed immediately, so a killed session still yields a
+/// replayable file.
+pub struct CastRecorder {
+    file: File,
+    start: Instant,
+}
+
+impl CastRecorder {
+    pub fn create<P: AsRef<Path>>(path: P, width: u16, height: u16) -> Result<CastRecorder> {
+        let mut file = File::create(path.as_ref())
+            .with_context(|| format!("Failed to create cast file {:?}", path.as_ref()))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("system clock before UNIX epoch")?
+            .as_secs();
+        let env_str = json::stringify(env::vars().collect::<HashMap<String, String>>());
+        writeln!(
+            file,
+            "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}, \"env\": {}}}",
+            width, height, timestamp, env_str
+        )
+        .context("Failed to write cast header")?;
+        file.flush().context("Failed to flush cast header")?;
+        Ok(CastRecorder {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, kind: &str, data: &[u8]) -> Result<()> {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let text = String::from_utf8_lossy(data);
+        writeln!(
+            self.file,
+            "[{}, {}, {}]",
+            elapsed,
+            json::stringify(kind),
+            json::stringify(text.as_ref())
+        )
+        .context("Failed to write cast event")?;
+        self.file.flush().context("Failed to flush cast event")?;
+        Ok(())
+    }
+
+    pub fn record_output(&mut self, data: &[u8]) -> Result<()> {
+        self.record("o", data)
+    }
+
+    pub fn record_input(&mut self, data: &[u8]) -> Result<()> {
+        self.record("i", data)
+    }
+}
+
+const ESC: u8 = 0x1B;
+const BEL: u8 = 0x07;
+
+/// One parsed unit of the pty's output stream, as handed to a `Filter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A run of plain bytes with no escape sequences.
+    Text(Vec<u8>),
+    /// A CSI sequence: `ESC [` ... a final byte in `0x40..=0x7E`, inclusive of both ends.
+    Csi(Vec<u8>),
+    /// Any other ESC-introduced sequence: a lone `ESC` plus one byte, or an OSC (`ESC ]`)
+    /// terminated by `BEL` or the `ESC \` string terminator.
+    Escape(Vec<u8>),
+}
+
+impl Token {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Token::Text(b) | Token::Csi(b) | Token::Escape(b) => b,
+        }
+    }
+}
+
+/// Rewrites data flowing between the interpreter's PTY and the user's terminal — e.g. to strip
+/// colors, remap SGR codes, inject a prompt prefix, or filter OSC title updates. `on_child_data`
+/// sees bytes read from the PTY before they reach stdout; `on_user_data` sees stdin bytes before
+/// they're written to the PTY. Each call receives one parsed unit's bytes — a run of plain text,
+/// or a whole CSI/escape sequence — never a sequence split across two calls, since `AnsiParser`
+/// buffers an incomplete trailing sequence until it's complete. The default (no filter)
+/// passthrough forwards every unit unchanged; see `proxy_loop`.
+pub trait Filter {
+    fn on_child_data(&mut self, data: &[u8]) -> Vec<u8>;
+    fn on_user_data(&mut self, data: &[u8]) -> Vec<u8>;
+}
+
+/// A `Filter` that rewrites nothing; forces data through the parser without changing behavior,
+/// useful when a caller wants the tokenization boundaries but no actual rewriting.
+pub struct PassthroughFilter;
+
+impl Filter for PassthroughFilter {
+    fn on_child_data(&mut self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn on_user_data(&mut self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+}
+
+/// Incrementally splits a byte stream into `Token`s. Escape sequences can be split across
+/// `read()` boundaries, so an incomplete trailing sequence is retained in `carry` and
+/// re-parsed once more bytes arrive, instead of being misread as plain text.
+#[derive(Default)]
+struct AnsiParser {
+    carry: Vec<u8>,
+}
+
+impl AnsiParser {
+    fn feed(&mut self, data: &[u8]) -> Vec<Token> {
+        if !self.carry.is_empty() {
+            self.carry.extend_from_slice(data);
+        }
+        let working: &[u8] = if self.carry.is_empty() { data } else { &self.carry };
+
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < working.len() {
+            if working[i] == ESC {
+                match Self::parse_escape(&working[i..]) {
+                    Some((consumed, token)) => {
+                        tokens.push(token);
+                        i += consumed;
+                    }
+                    None => {
+                        // Incomplete escape sequence: keep it for the next feed() call.
+                        let tail = working[i..].to_vec();
+                        self.carry = tail;
+                        return tokens;
+                    }
+                }
+            } else {
+                let start = i;
+                while i < working.len() && working[i] != ESC {
+                    i += 1;
+                }
+                tokens.push(Token::Text(working[start..i].to_vec()));
+            }
+        }
+        self.carry.clear();
+        tokens
+    }
+
+    // Parse one ESC-introduced sequence starting at `slice[0] == ESC`. Returns the number of
+    // bytes consumed and the token, or `None` if `slice` doesn't yet contain a complete one.
+    fn parse_escape(slice: &[u8]) -> Option<(usize, Token)> {
+        if slice.len() < 2 {
+            return None;
+        }
+        match slice[1] {
+            b'[' => {
+                // CSI: parameter/intermediate bytes until a final byte in 0x40..=0x7E.
+                let mut j = 2;
+                while j < slice.len() {
+                    if (0x40..=0x7E).contains(&slice[j]) {
+                        return Some((j + 1, Token::Csi(slice[..=j].to_vec())));
+                    }
+                    j += 1;
+                }
+                None
+            }
+            b']' => {
+                // OSC: terminated by BEL or the ESC \ string terminator.
+                let mut j = 2;
+                while j < slice.len() {
+                    if slice[j] == BEL {
+                        return Some((j + 1, Token::Escape(slice[..=j].to_vec())));
+                    }
+                    if slice[j] == ESC {
+                        if j + 1 < slice.len() {
+                            if slice[j + 1] == b'\\' {
+                                return Some((j + 2, Token::Escape(slice[..j + 2].to_vec())));
+                            }
+                        } else {
+                            // Could be the start of the ST terminator; wait for more data.
+                            return None;
+                        }
+                    }
+                    j += 1;
+                }
+                None
+            }
+            _ => Some((2, Token::Escape(slice[..2].to_vec()))),
+        }
+    }
+}
+
 /// Set up SIGWINCH signal handling via a UnixStream pair and register with signal_hook.
-fn setup_sigwinch_stream() -> Result<UnixStream> {
+/// Returns the `SigId` alongside the read end so the caller can unregister it once the proxy
+/// loop is done with the PTY, instead of leaving it registered for the rest of the process.
+fn setup_sigwinch_stream() -> Result<(SigId, UnixStream)> {
     let (sigwinch_r, sigwinch_w) =
         UnixStream::pair().context("Failed to create UnixStream pair for signals")?;
     sigwinch_r
@@ -80,22 +299,36 @@ fn setup_sigwinch_stream() -> Result<UnixStream> {
     sigwinch_w
         .set_nonblocking(true)
         .context("Failed to set sigwinch_w to non-blocking")?;
-    pipe::register(SIGWINCH, sigwinch_w).context("Failed to register SIGWINCH with pipe")?;
-    Ok(sigwinch_r)
+    let id = pipe::register(SIGWINCH, sigwinch_w).context("Failed to register SIGWINCH with pipe")?;
+    Ok((id, sigwinch_r))
 }
 
-/// Main polling loop using high-level I/O on pty_file.
-fn proxy_loop(
-    mut pty: Option<PtyMaster>,
+/// Main polling loop using high-level I/O on pty_file. `sigwinch_r` is `None` in
+/// non-interactive/pass-through mode (piped stdin/stdout): there's no real terminal to resize
+/// or catch SIGWINCH on, so that source is simply left out of the poll set. `filter`, if
+/// given, rewrites each parsed token of the pty's output before it's written to stdout; with
+/// no filter, pty output is copied to stdout byte-for-byte.
+///
+/// Generic over `P` rather than hardcoded to `PtyMaster` so the same loop can drive a remote
+/// interpreter's PTY relayed over a `TcpStream` (see `hsserver::remote`): both implement
+/// `Read + Write + AsFd`, and the loop only ever needs those three capabilities.
+fn proxy_loop<P: Read + Write + AsFd>(
+    mut pty: Option<P>,
     mut stdin: Option<Stdin>,
     mut stdout: Stdout,
-    mut sigwinch_r: UnixStream,
+    mut sigwinch_r: Option<UnixStream>,
+    mut filter: Option<&mut dyn Filter>,
+    mut recorder: Option<&mut CastRecorder>,
 ) -> Result<()> {
     let mut buf = [0u8; 1024];
+    let mut parser = AnsiParser::default();
+    let mut user_parser = AnsiParser::default();
 
     loop {
         let mut fds = Vec::with_capacity(3);
-        fds.push(PollFd::new(sigwinch_r.as_fd(), PollFlags::POLLIN));
+        if let Some(sigwinch_fd) = &sigwinch_r {
+            fds.push(PollFd::new(sigwinch_fd.as_fd(), PollFlags::POLLIN));
+        }
         if let Some(pty_fd) = &pty {
             fds.push(PollFd::new(pty_fd.as_fd(), PollFlags::POLLIN));
         }
@@ -105,15 +338,26 @@ fn proxy_loop(
 
         poll(&mut fds, PollTimeout::NONE).context("Failed to poll file descriptors")?;
 
-        let sigwinch_revents = fds[0].revents();
-        let pty_revents = fds.get(1).and_then(|f| f.revents());
-        let stdin_revents = fds.get(2).and_then(|f| f.revents());
+        let mut next = 0;
+        let mut take_revents = |present: bool| {
+            if !present {
+                return None;
+            }
+            let revents = fds[next].revents();
+            next += 1;
+            revents
+        };
+        let sigwinch_revents = take_revents(sigwinch_r.is_some());
+        let pty_revents = take_revents(pty.is_some());
+        let stdin_revents = take_revents(stdin.is_some());
 
         // Handle SIGWINCH events
         if let Some(revents) = sigwinch_revents {
             if revents.contains(PollFlags::POLLIN) {
                 let mut sbuf = [0u8; 1];
                 sigwinch_r
+                    .as_mut()
+                    .unwrap()
                     .read_exact(&mut sbuf)
                     .context("sigwinch_r.read_exact error")?;
                 if let Some(pty_fd) = &mut pty {
@@ -133,7 +377,17 @@ fn proxy_loop(
                     // Interpreter exited
                     break;
                 }
-                stdout.write_all(&buf[..n])?;
+                if let Some(rec) = &mut recorder {
+                    rec.record_output(&buf[..n])?;
+                }
+                match &mut filter {
+                    Some(f) => {
+                        for token in parser.feed(&buf[..n]) {
+                            stdout.write_all(&f.on_child_data(token.bytes()))?;
+                        }
+                    }
+                    None => stdout.write_all(&buf[..n])?,
+                }
                 stdout.flush()?;
             }
         }
@@ -149,9 +403,21 @@ fn proxy_loop(
                     }
                     stdin = None;
                 } else if let Some(pty_fd) = &mut pty {
-                    pty_fd
-                        .write_all(&buf[..n])
-                        .context("proxy write to pty error")?
+                    if let Some(rec) = &mut recorder {
+                        rec.record_input(&buf[..n])?;
+                    }
+                    match &mut filter {
+                        Some(f) => {
+                            for token in user_parser.feed(&buf[..n]) {
+                                pty_fd
+                                    .write_all(&f.on_user_data(token.bytes()))
+                                    .context("proxy write to pty error")?;
+                            }
+                        }
+                        None => pty_fd
+                            .write_all(&buf[..n])
+                            .context("proxy write to pty error")?,
+                    }
                 }
             }
         }
@@ -160,21 +426,173 @@ fn proxy_loop(
     Ok(())
 }
 
-/// Updated `do_proxy` to accept a reference to a `std::fs::File` and use high-level I/O.
-pub fn do_proxy(_guard: &TerminalModeGuard, pty: PtyMaster) -> Result<()> {
+/// Proxy the interpreter's PTY to our own stdin/stdout until it exits. When `guard` reports a
+/// real terminal, this also mirrors window size and SIGWINCH onto the PTY; otherwise (piped
+/// stdin/stdout, e.g. CI or `echo code | py-hotstart`) it skips straight to pass-through byte
+/// forwarding, since raw mode and window size have no meaning there. `filter`, if given,
+/// rewrites the PTY's output before it reaches stdout; see `Filter`. `record_path`, if given,
+/// tees the session into an asciinema v2 cast file at that path; see `CastRecorder`.
+///
+/// `pty` only needs to be `Read + Write + AsFd`, not literally a `PtyMaster`: a remote take
+/// (see `hsclient::client::take_interpreter_remote`) hands this a `TcpStream` carrying the PTY bytes relayed from the
+/// remote host instead, and the loop treats it identically.
+pub fn do_proxy<P: Read + Write + AsFd>(
+    guard: &TerminalModeGuard,
+    pty: P,
+    filter: Option<&mut dyn Filter>,
+    record_path: Option<&Path>,
+) -> Result<()> {
     let stdin = std::io::stdin();
     let stdout = std::io::stdout();
 
-    // Set up signal handling for SIGWINCH
-    let sigwinch_r = setup_sigwinch_stream()?;
+    let sigwinch = if guard.is_tty() {
+        if let Err(e) = sync_winsize(stdout.as_fd(), pty.as_fd()) {
+            eprintln!("Failed to sync window size: {}", e);
+        }
+        Some(setup_sigwinch_stream()?)
+    } else {
+        None
+    };
+    let (sigwinch_id, sigwinch_r) = match sigwinch {
+        Some((id, r)) => (Some(id), Some(r)),
+        None => (None, None),
+    };
 
-    // Sync window size initially
-    if let Err(e) = sync_winsize(stdout.as_fd(), pty.as_fd()) {
-        eprintln!("Failed to sync window size: {}", e);
-    }
+    let mut recorder = match record_path {
+        Some(path) => {
+            let ws = get_winsize(pty.as_fd());
+            Some(CastRecorder::create(path, ws.ws_col, ws.ws_row)?)
+        }
+        None => None,
+    };
 
     // Run the polling loop using high-level operations
-    proxy_loop(Some(pty), Some(stdin), stdout, sigwinch_r)?;
+    let result = proxy_loop(
+        Some(pty),
+        Some(stdin),
+        stdout,
+        sigwinch_r,
+        filter,
+        recorder.as_mut(),
+    );
 
-    Ok(())
+    // Stop listening for resizes now that the PTY this handler resizes is gone, so a SIGWINCH
+    // arriving during the raw-mode teardown that follows can't reach for a closed fd.
+    if let Some(id) = sigwinch_id {
+        signal_hook::low_level::unregister(id);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod ansi_parser_tests {
+    use super::{AnsiParser, Token};
+
+    #[test]
+    fn test_plain_text() {
+        let mut parser = AnsiParser::default();
+        assert_eq!(parser.feed(b"hello"), vec![Token::Text(b"hello".to_vec())]);
+    }
+
+    #[test]
+    fn test_csi_in_one_feed() {
+        let mut parser = AnsiParser::default();
+        assert_eq!(parser.feed(b"\x1b[31m"), vec![Token::Csi(b"\x1b[31m".to_vec())]);
+    }
+
+    #[test]
+    fn test_csi_split_across_feeds() {
+        // ESC [ 3 1 m split at every possible boundary should still yield one CSI token once
+        // the sequence is complete, and nothing before that.
+        let full = b"\x1b[31m";
+        for split in 1..full.len() {
+            let mut parser = AnsiParser::default();
+            let first = parser.feed(&full[..split]);
+            assert!(first.is_empty(), "split at {split}: expected no tokens yet, got {first:?}");
+            let second = parser.feed(&full[split..]);
+            assert_eq!(second, vec![Token::Csi(full.to_vec())], "split at {split}");
+        }
+    }
+
+    #[test]
+    fn test_csi_split_one_byte_at_a_time() {
+        let full = b"\x1b[1;31m";
+        let mut parser = AnsiParser::default();
+        let mut tokens = Vec::new();
+        for &b in full {
+            tokens.extend(parser.feed(&[b]));
+        }
+        assert_eq!(tokens, vec![Token::Csi(full.to_vec())]);
+    }
+
+    #[test]
+    fn test_text_then_csi_then_text_in_one_feed() {
+        let mut parser = AnsiParser::default();
+        let tokens = parser.feed(b"hi\x1b[1mbye");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Text(b"hi".to_vec()),
+                Token::Csi(b"\x1b[1m".to_vec()),
+                Token::Text(b"bye".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_osc_terminated_by_bel_in_one_feed() {
+        let mut parser = AnsiParser::default();
+        let full = b"\x1b]0;title\x07";
+        assert_eq!(parser.feed(full), vec![Token::Escape(full.to_vec())]);
+    }
+
+    #[test]
+    fn test_osc_terminated_by_bel_split_across_feeds() {
+        let full = b"\x1b]0;title\x07";
+        for split in 1..full.len() {
+            let mut parser = AnsiParser::default();
+            let first = parser.feed(&full[..split]);
+            assert!(first.is_empty(), "split at {split}: expected no tokens yet, got {first:?}");
+            let second = parser.feed(&full[split..]);
+            assert_eq!(second, vec![Token::Escape(full.to_vec())], "split at {split}");
+        }
+    }
+
+    #[test]
+    fn test_osc_terminated_by_st_in_one_feed() {
+        let mut parser = AnsiParser::default();
+        let full = b"\x1b]0;title\x1b\\";
+        assert_eq!(parser.feed(full), vec![Token::Escape(full.to_vec())]);
+    }
+
+    #[test]
+    fn test_osc_terminated_by_st_split_across_feeds() {
+        let full = b"\x1b]0;title\x1b\\";
+        for split in 1..full.len() {
+            let mut parser = AnsiParser::default();
+            let first = parser.feed(&full[..split]);
+            assert!(first.is_empty(), "split at {split}: expected no tokens yet, got {first:?}");
+            let second = parser.feed(&full[split..]);
+            assert_eq!(second, vec![Token::Escape(full.to_vec())], "split at {split}");
+        }
+    }
+
+    #[test]
+    fn test_osc_st_split_right_after_leading_esc_of_terminator() {
+        // The ESC of the "ESC \" terminator arrives alone, with the backslash in the next feed:
+        // parse_escape must recognize this as "incomplete" rather than misreading it as some
+        // other single-byte escape.
+        let mut parser = AnsiParser::default();
+        let first = parser.feed(b"\x1b]0;title\x1b");
+        assert!(first.is_empty());
+        let second = parser.feed(b"\\");
+        assert_eq!(second, vec![Token::Escape(b"\x1b]0;title\x1b\\".to_vec())]);
+    }
+
+    #[test]
+    fn test_lone_escape_not_csi_or_osc() {
+        let mut parser = AnsiParser::default();
+        assert_eq!(parser.feed(b"\x1bc"), vec![Token::Escape(b"\x1bc".to_vec())]);
+    }
 }
@@ -12,6 +12,7 @@ use std::io::Write;
 use std::os::fd::RawFd;
 use std::path::{Path, PathBuf};
 use std::process;
+use std::time::{Duration, Instant};
 
 const LOGFILE: &str = "/tmp/py-hotstart.log";
 
@@ -91,13 +92,17 @@ pub struct PidFileGuard {
 }
 
 impl PidFileGuard {
+    // Checks the PID on disk with `process_is_alive` before trusting it. The pidfd is opened
+    // from the bare PID at check time, so a PID file left behind by a dead daemon whose PID has
+    // since been reassigned to a live, unrelated process reads as alive and is not removed.
     pub fn test<P: AsRef<Path>>(path: P) -> Result<Option<Pid>> {
         if path.as_ref().exists() {
             let contents = read_to_string(path.as_ref())?;
             let pid_str = contents.trim();
             if let Ok(other_pid) = pid_str.parse::<i32>() {
+                let other_pid = Pid::from_raw(other_pid);
                 if process_is_alive(other_pid)? {
-                    return Ok(Some(Pid::from_raw(other_pid)));
+                    return Ok(Some(other_pid));
                 }
             }
             // Otherwise, treat it as stale PID file
@@ -144,8 +149,74 @@ impl Drop for PidFileGuard {
     }
 }
 
-fn process_is_alive(pid: i32) -> Result<bool> {
-    match kill(Pid::from_raw(pid), None) {
+// Open a pidfd for `pid`, a reuse-proof handle to that exact process: unlike a bare PID,
+// which the kernel can hand to an unrelated process once this one exits and is reaped, the
+// pidfd keeps referring to the same process for as long as it's open. Returns `None` on
+// kernels without pidfd_open(2) (pre-5.3) so callers can fall back to kill(pid, 0).
+fn pidfd_open(pid: Pid) -> Option<RawFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    if fd < 0 {
+        None
+    } else {
+        Some(fd as RawFd)
+    }
+}
+
+// A pidfd becomes readable (POLLIN) once its process has exited, so a zero-timeout poll is a
+// non-blocking, race-free liveness check: "readable" really does mean *this* process exited,
+// never a different process that happened to reuse its old PID.
+fn pidfd_is_alive(fd: RawFd) -> Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ret = unsafe { libc::poll(&mut pfd, 1, 0) };
+    if ret < 0 {
+        return Err(anyhow!(
+            "poll on pidfd failed: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(pfd.revents & libc::POLLIN == 0)
+}
+
+// SIGTERM a process we don't own (so there's no waitpid to block on) and poll its liveness via
+// `process_is_alive` until it's gone or `timeout` elapses, escalating to SIGKILL if it's still
+// around past the deadline. Used by the hard-restart fallback, which is killing a previous
+// daemon process rather than one of the supervisor's own children.
+pub fn kill_with_timeout(pid: Pid, timeout: Duration) -> Result<()> {
+    kill(pid, nix::sys::signal::SIGTERM).ok();
+
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if !process_is_alive(pid)? {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    if process_is_alive(pid)? {
+        kill(pid, nix::sys::signal::SIGKILL).ok();
+        while process_is_alive(pid)? {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+    Ok(())
+}
+
+// Exposed crate-wide so callers besides the PID file (e.g. the interpreter pool) can check
+// liveness without re-parsing a PID file or duplicating the pidfd/kill(pid, 0) fallback.
+pub(crate) fn process_is_alive(pid: Pid) -> Result<bool> {
+    if let Some(fd) = pidfd_open(pid) {
+        let alive = pidfd_is_alive(fd);
+        close(fd).ok();
+        return alive;
+    }
+
+    // No pidfd_open(2) on this kernel: fall back to the PID-reuse-vulnerable kill(pid, 0)
+    // check, which is the best available without it.
+    match kill(pid, None) {
         Ok(_) => Ok(true),
         Err(Errno::ESRCH) => Ok(false),
         Err(e) => Err(anyhow!("process_is_alive: kill error {}", e)),
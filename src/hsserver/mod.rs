@@ -0,0 +1,4 @@
+pub mod daemon;
+pub mod remote;
+pub mod server;
+pub mod supervisor;
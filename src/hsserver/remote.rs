@@ -0,0 +1,165 @@
+use crate::hsserver::supervisor::{CommandSpec, Supervisor};
+use crate::interpreter::ChildId;
+use crate::remote::{read_json_frame, write_json_frame};
+use anyhow::{bail, Context, Result};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsFd;
+use std::str::FromStr;
+
+/// Shared secret every remote request must echo back in its `token` field (read by both
+/// `serve_remote` here and `hsclient::client`'s `*_remote` callers from this same env var).
+/// There's no PTY-fd-handoff or SO_PEERCRED equivalent to lean on over a network connection the
+/// way the Unix-socket `server` leans on `check_peer_uid`, so this is the only thing standing
+/// between "anyone who can reach this port" and arbitrary command execution as this process's
+/// user; serve_remote refuses to start at all without it.
+pub const REMOTE_TOKEN_ENV: &str = "PY_HOTSTART_REMOTE_TOKEN";
+
+/// Runs a TCP-based interpreter host: accepts connections on `addr` and serves the same TAKE/
+/// EXITCODE pair the Unix-socket `server` does. Since SCM_RIGHTS fds can't cross a network
+/// connection, a TAKE doesn't hand back the PTY master fd; instead this keeps it here and
+/// relays raw bytes over the same TCP connection, which `do_proxy` treats identically to a
+/// local PTY (it only needs `Read + Write + AsFd`, not a literal `PtyMaster`). EXITCODE is a
+/// second, short-lived connection against this same long-lived `Supervisor`.
+///
+/// Deliberately has no warm pool: every TAKE forks a fresh interpreter on the spot. A remote
+/// host is for offloading occasional bursts to a beefier machine, not for matching the local
+/// pool's O(1) handoff latency, so a second background refill loop isn't worth the complexity
+/// here.
+pub fn serve_remote(addr: &str) -> Result<()> {
+    // Fail closed: refuse to listen on the network at all rather than serve unauthenticated
+    // command execution to whatever can reach `addr`.
+    let token = env::var(REMOTE_TOKEN_ENV).with_context(|| {
+        format!(
+            "refusing to start: set {} to a shared secret before using --remote-serve",
+            REMOTE_TOKEN_ENV
+        )
+    })?;
+    if token.is_empty() {
+        bail!("{} must not be empty", REMOTE_TOKEN_ENV);
+    }
+
+    let listener = TcpListener::bind(addr)
+        .with_context(|| format!("Failed to bind remote listener on {}", addr))?;
+    eprintln!("Listening for remote interpreter requests on {}", addr);
+
+    let mut supervisor = Supervisor::new();
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Accept failed: {}", e);
+                continue;
+            }
+        };
+        if let Err(err) = handle_connection(&mut stream, &mut supervisor, &token) {
+            eprintln!("Error handling remote request: {:?}", err);
+            let _ = write_json_frame(&mut stream, &json::object! { error: err.to_string() });
+        }
+    }
+    Ok(())
+}
+
+// Constant-time byte comparison: a shared secret should never be checked with a short-circuiting
+// `==`, whose early-exit-on-first-mismatch timing can leak how many leading bytes a guess got
+// right. No crypto crate is available in this tree, so this simple XOR-accumulate compare is the
+// proportionate fix rather than pulling one in for a single equality check.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+fn handle_connection(stream: &mut TcpStream, supervisor: &mut Supervisor, token: &str) -> Result<()> {
+    let req = read_json_frame(stream).context("failed to read remote request")?;
+    let given_token = req["token"].as_str().unwrap_or("");
+    if !constant_time_eq(given_token.as_bytes(), token.as_bytes()) {
+        bail!("unauthorized: bad or missing token");
+    }
+    match req["cmd"].as_str().context("missing cmd")? {
+        "TAKE" => handle_take(stream, &req, supervisor),
+        "EXITCODE" => handle_exit_code(stream, &req, supervisor),
+        other => bail!("unknown remote command {:?}", other),
+    }
+}
+
+fn handle_take(
+    stream: &mut TcpStream,
+    req: &json::JsonValue,
+    supervisor: &mut Supervisor,
+) -> Result<()> {
+    let prelude = req["prelude"].as_str().map(str::to_string);
+    let spec = if req["spec"].is_null() {
+        CommandSpec::default()
+    } else {
+        CommandSpec::from_json(&req["spec"])?
+    };
+    let instructions = req["instructions"].as_str().unwrap_or("");
+
+    let mut interp = supervisor.spawn_interpreter(prelude.as_deref(), &spec)?;
+    interp.unsupervise()?;
+    interp.run_instructions(instructions)?;
+
+    write_json_frame(stream, &json::object! { id: interp.id().to_string() })
+        .context("failed to write TAKE response")?;
+
+    bridge_pty(interp.pty_master_fd(), stream)?;
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    Ok(())
+}
+
+fn handle_exit_code(
+    stream: &mut TcpStream,
+    req: &json::JsonValue,
+    supervisor: &mut Supervisor,
+) -> Result<()> {
+    let id_str = req["id"].as_str().context("missing id")?;
+    let child_id = ChildId::from_str(id_str)?;
+    let exit_code = supervisor.get_exit_code(child_id)?;
+    write_json_frame(stream, &json::object! { exit_code: exit_code })
+        .context("failed to write EXITCODE response")
+}
+
+// Bidirectionally copy bytes between `pty` and `stream` until either side hits EOF: the
+// interpreter exiting closes the PTY slave, which shows up here as a `read` of 0, and we then
+// shut down our end of `stream` so the client's `do_proxy` sees the same EOF it would from a
+// local PTY master closing. Mirrors `proxy::proxy_loop`'s poll-based copy, minus the
+// filter/recorder/SIGWINCH machinery that only makes sense with a real local terminal attached.
+fn bridge_pty(pty: &File, stream: &mut TcpStream) -> Result<()> {
+    let mut pty = pty;
+    let mut buf = [0u8; 1024];
+    loop {
+        let mut fds = [
+            PollFd::new(pty.as_fd(), PollFlags::POLLIN),
+            PollFd::new(stream.as_fd(), PollFlags::POLLIN),
+        ];
+        poll(&mut fds, PollTimeout::NONE).context("poll failed")?;
+
+        if fds[0].revents().map_or(false, |r| r.contains(PollFlags::POLLIN)) {
+            let n = pty.read(&mut buf).context("failed to read from pty")?;
+            if n == 0 {
+                break;
+            }
+            stream
+                .write_all(&buf[..n])
+                .context("failed to write to remote stream")?;
+        }
+        if fds[1].revents().map_or(false, |r| r.contains(PollFlags::POLLIN)) {
+            let n = stream.read(&mut buf).context("failed to read from remote stream")?;
+            if n == 0 {
+                break;
+            }
+            pty.write_all(&buf[..n]).context("failed to write to pty")?;
+        }
+    }
+    Ok(())
+}
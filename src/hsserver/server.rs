@@ -1,16 +1,23 @@
 use crate::hsserver::daemon::{daemonize, PidFileGuard};
-use crate::hsserver::supervisor::Supervisor;
-use crate::interpreter::{ChildId, Interpreter};
-use crate::sendfd::SendWithFd;
+use crate::hsserver::supervisor::{CommandSpec, Supervisor};
+use crate::interpreter::ChildId;
+use crate::sendfd::{read_frame, write_frame};
 use anyhow::{bail, Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
 use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
-use nix::unistd::{ForkResult, Pid};
-use signal_hook::consts::{SIGCHLD, SIGINT, SIGTERM};
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use nix::unistd::{close, execv, geteuid, ForkResult, Pid};
+use signal_hook::consts::{SIGCHLD, SIGHUP, SIGINT, SIGTERM};
 use signal_hook::low_level::pipe;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
 use std::fs;
-use std::io::{Read, Write};
-use std::os::fd::AsFd;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::os::fd::{AsFd, AsRawFd, RawFd};
 use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::FromRawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
 use std::process;
@@ -21,41 +28,75 @@ use super::daemon::kill_with_timeout;
 
 pub const SOCKET_PATH: &str = "/tmp/py_hotstart.sock";
 const PIDFILE_PATH: &str = "/tmp/py_hotstart.pid";
+const UPGRADE_STATE_PATH: &str = "/tmp/py_hotstart.upgrade";
+const UPGRADE_LISTENER_FD_ENV: &str = "PY_HOTSTART_UPGRADE_LISTENER_FD";
+const UPGRADE_STATE_ENV: &str = "PY_HOTSTART_UPGRADE_STATE";
+// Comma-separated list of additional uids (beyond the server's own effective uid) allowed to
+// issue requests, e.g. "1000,1001". Read once at startup; unset means "only our own uid".
+const ALLOWED_UIDS_ENV: &str = "PY_HOTSTART_ALLOWED_UIDS";
+
+// Split out of `ServerState::parse_allowed_uids` so it can be unit tested without touching
+// process-global env state.
+fn parse_allowed_uids_str(raw: &str) -> Result<Vec<u32>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<u32>().context("invalid uid in PY_HOTSTART_ALLOWED_UIDS"))
+        .collect()
+}
+
+// Split out of `ServerState::check_peer_uid` so it can be unit tested without a real
+// `UnixStream` pair (whose peer uid is always the test process's own uid, making "mismatched
+// uid rejected" untestable through the socket path alone).
+fn is_peer_uid_allowed(peer_uid: u32, own_uid: u32, allowed_uids: &[u32]) -> bool {
+    peer_uid == own_uid || allowed_uids.contains(&peer_uid)
+}
 
 struct ServerState {
     listener: UnixListener,
-    current_interpreter: Option<Interpreter>,
     prelude_code: Option<String>,
+    command_spec: CommandSpec,
     supervisor: Supervisor,
     sigchld_fd: UnixStream,
     sigterm_fd: UnixStream,
+    sighup_fd: UnixStream,
+    // Uids (in addition to our own effective uid) allowed to issue requests. Read once at
+    // startup from ALLOWED_UIDS_ENV.
+    allowed_uids: Vec<u32>,
+    // Connections accepted off the (non-blocking) listener but not yet serviced: each run_one
+    // call polls all of them alongside the listener and signal fds, so several clients can be
+    // mid-request at once instead of one slow client blocking everyone behind it in the accept
+    // queue. Each connection still carries exactly one request/response (see `handle`), so it's
+    // removed here as soon as it's been handled.
+    pending: Vec<UnixStream>,
+    // EXITCODE requests parked here because the child they asked about hadn't exited yet;
+    // answered from `answer_pending_exitcode` as soon as a later SIGCHLD reaps it, so EXITCODE
+    // never blocks the single-threaded event loop waiting on one slow-to-exit child.
+    pending_exitcode: HashMap<ChildId, Vec<UnixStream>>,
 }
 
 impl ServerState {
     fn new() -> Result<ServerState> {
+        if let Ok(fd_str) = env::var(UPGRADE_LISTENER_FD_ENV) {
+            return Self::from_upgrade(&fd_str);
+        }
+        Self::bind_fresh()
+    }
+
+    fn bind_fresh() -> Result<ServerState> {
         if Path::new(SOCKET_PATH).exists() {
             fs::remove_file(SOCKET_PATH).ok();
         }
 
         let listener =
             UnixListener::bind(SOCKET_PATH).context("Failed to bind Unix domain socket")?;
+        listener
+            .set_nonblocking(true)
+            .context("Failed to set listener to non-blocking")?;
 
         eprintln!("Listening on {}", SOCKET_PATH);
 
-        let (sigchld_fd, sigterm_fd) = {
-            let (sigchld_r, sigchld_w) = UnixStream::pair()?;
-            let (sigterm_r, sigterm_w) = UnixStream::pair()?;
-            let sigint_w = sigterm_w.try_clone()?;
-            for socket in &[&sigchld_r, &sigchld_w, &sigterm_r, &sigterm_w, &sigint_w] {
-                socket
-                    .set_nonblocking(true)
-                    .context("Failed to set socket to non-blocking")?;
-            }
-            pipe::register(SIGCHLD, sigchld_w)?;
-            pipe::register(SIGTERM, sigterm_w)?;
-            pipe::register(SIGINT, sigint_w)?;
-            (sigchld_r, sigterm_r)
-        };
+        let (sigchld_fd, sigterm_fd, sighup_fd) = Self::setup_signal_fds()?;
 
         let mut perms = fs::metadata(SOCKET_PATH)?.permissions();
         // Adjust permissions if needed (e.g. 0700)
@@ -64,26 +105,110 @@ impl ServerState {
 
         Ok(ServerState {
             listener,
-            current_interpreter: None,
             prelude_code: None,
+            command_spec: CommandSpec::default(),
             supervisor: Supervisor::new(),
             sigchld_fd,
             sigterm_fd,
+            sighup_fd,
+            allowed_uids: Self::parse_allowed_uids()?,
+            pending: Vec::new(),
+            pending_exitcode: HashMap::new(),
         })
     }
 
-    fn ensure_interpreter(&mut self) -> Result<()> {
-        if self.current_interpreter.is_none() {
-            self.current_interpreter = Some(
-                self.supervisor
-                    .spawn_interpreter(self.prelude_code.as_deref())?,
-            );
+    // Reconstructs state left behind by a sibling process that just re-exec'd into us via the
+    // UPGRADE command: the listener fd is inherited rather than re-bound (SOCKET_PATH is never
+    // unlinked here, since the inherited listener is still bound to it), and the Supervisor is
+    // rebuilt from the state file `handle_upgrade` wrote just before execve().
+    fn from_upgrade(fd_str: &str) -> Result<ServerState> {
+        let fd: RawFd = fd_str
+            .parse()
+            .context("invalid listener fd in upgrade env var")?;
+        let listener = unsafe { UnixListener::from_raw_fd(fd) };
+        // O_NONBLOCK is a property of the open file description, so it normally survives
+        // execve() along with the fd itself; set it again anyway so a re-exec from an older
+        // binary predating non-blocking accept doesn't silently inherit a blocking listener.
+        listener
+            .set_nonblocking(true)
+            .context("Failed to set inherited listener to non-blocking")?;
+        eprintln!("Resumed listening on {} (inherited fd {})", SOCKET_PATH, fd);
+
+        let state_path = env::var(UPGRADE_STATE_ENV).context("missing upgrade state path")?;
+        let (prelude_code, command_spec, mut supervisor) =
+            Supervisor::from_upgrade_state(&state_path)
+                .context("Failed to rebuild supervisor from upgrade state")?;
+        let _ = fs::remove_file(&state_path);
+        // Consumed: clear so these don't leak into some later, unrelated re-exec of this process.
+        env::remove_var(UPGRADE_LISTENER_FD_ENV);
+        env::remove_var(UPGRADE_STATE_ENV);
+
+        // Reap anything that exited during the exec gap before accepting new connections.
+        supervisor.handle_sigchld()?;
+
+        let (sigchld_fd, sigterm_fd, sighup_fd) = Self::setup_signal_fds()?;
+
+        Ok(ServerState {
+            listener,
+            prelude_code,
+            command_spec,
+            supervisor,
+            sigchld_fd,
+            sigterm_fd,
+            sighup_fd,
+            allowed_uids: Self::parse_allowed_uids()?,
+            pending: Vec::new(),
+            pending_exitcode: HashMap::new(),
+        })
+    }
+
+    fn parse_allowed_uids() -> Result<Vec<u32>> {
+        match env::var(ALLOWED_UIDS_ENV) {
+            Ok(raw) => parse_allowed_uids_str(&raw),
+            Err(_) => Ok(Vec::new()),
         }
-        Ok(())
+    }
+
+    // Reject any request whose connecting peer isn't our own effective uid (or on the optional
+    // allowlist from ALLOWED_UIDS_ENV). Without this, the 0600 mode on SOCKET_PATH is the only
+    // thing stopping another local user from claiming a handed-off PTY master fd and taking over
+    // the interpreter running behind it.
+    fn check_peer_uid(&self, stream: &UnixStream) -> Result<()> {
+        let creds = getsockopt(stream, PeerCredentials).context("SO_PEERCRED lookup failed")?;
+        let peer_uid = creds.uid();
+        if is_peer_uid_allowed(peer_uid, geteuid().as_raw(), &self.allowed_uids) {
+            eprintln!("Accepted request from uid={} pid={}", peer_uid, creds.pid());
+            return Ok(());
+        }
+        bail!(
+            "rejected request from unauthorized uid={} pid={}",
+            peer_uid,
+            creds.pid()
+        );
+    }
+
+    fn setup_signal_fds() -> Result<(UnixStream, UnixStream, UnixStream)> {
+        let (sigchld_r, sigchld_w) = UnixStream::pair()?;
+        let (sigterm_r, sigterm_w) = UnixStream::pair()?;
+        let sigint_w = sigterm_w.try_clone()?;
+        let (sighup_r, sighup_w) = UnixStream::pair()?;
+        for socket in &[
+            &sigchld_r, &sigchld_w, &sigterm_r, &sigterm_w, &sigint_w, &sighup_r, &sighup_w,
+        ] {
+            socket
+                .set_nonblocking(true)
+                .context("Failed to set socket to non-blocking")?;
+        }
+        pipe::register(SIGCHLD, sigchld_w)?;
+        pipe::register(SIGTERM, sigterm_w)?;
+        pipe::register(SIGINT, sigint_w)?;
+        pipe::register(SIGHUP, sighup_w)?;
+        Ok((sigchld_r, sigterm_r, sighup_r))
     }
 
     fn run(&mut self) -> Result<()> {
-        self.ensure_interpreter()?;
+        self.supervisor
+            .refill_pool(self.prelude_code.as_deref(), &self.command_spec)?;
 
         loop {
             match self.run_one() {
@@ -102,12 +227,22 @@ impl ServerState {
         let listener_fd = self.listener.as_fd();
         let sigchld_fd = self.sigchld_fd.as_fd();
         let sigterm_fd = self.sigterm_fd.as_fd();
+        let sighup_fd = self.sighup_fd.as_fd();
 
-        let mut fds = [
+        // Fixed fds first, then one entry per connection accepted but not yet handled (see
+        // `pending`), so a connection that hasn't sent anything yet never blocks the signal fds
+        // or the listener behind it. Once a connection is readable, `handle` reads its whole
+        // request with blocking reads, so a client that stops partway through a frame stalls
+        // this loop until it finishes or disconnects.
+        let mut fds: Vec<PollFd> = vec![
             PollFd::new(listener_fd, PollFlags::POLLIN),
             PollFd::new(sigchld_fd, PollFlags::POLLIN),
             PollFd::new(sigterm_fd, PollFlags::POLLIN),
+            PollFd::new(sighup_fd, PollFlags::POLLIN),
         ];
+        for stream in &self.pending {
+            fds.push(PollFd::new(stream.as_fd(), PollFlags::POLLIN));
+        }
 
         // Wait for input or signal
         loop {
@@ -128,6 +263,14 @@ impl ServerState {
         let sigterm_ready = fds[2]
             .revents()
             .map_or(false, |r| r.contains(PollFlags::POLLIN));
+        let sighup_ready = fds[3]
+            .revents()
+            .map_or(false, |r| r.contains(PollFlags::POLLIN));
+        let pending_ready: Vec<bool> = fds[4..]
+            .iter()
+            .map(|fd| fd.revents().map_or(false, |r| r.contains(PollFlags::POLLIN)))
+            .collect();
+        drop(fds);
 
         if sigchld_ready {
             let mut buf = [0u8; 1];
@@ -135,6 +278,7 @@ impl ServerState {
                 .read_exact(&mut buf)
                 .context("sigchld_fd.read_exact error")?;
             self.supervisor.handle_sigchld()?;
+            self.answer_pending_exitcode();
         }
 
         if sigterm_ready {
@@ -146,83 +290,300 @@ impl ServerState {
             return Ok(false);
         }
 
+        if sighup_ready {
+            let mut buf = [0u8; 1];
+            self.sighup_fd
+                .read_exact(&mut buf)
+                .context("sighup_fd.read_exact error")?;
+            eprintln!("Received SIGHUP, reloading in place.");
+            // A failed re-exec (e.g. the binary was removed from under us) shouldn't take the
+            // running server down; log it and keep serving with the current prelude/pool.
+            if let Err(e) = self.upgrade_in_place(|| Ok(())) {
+                eprintln!("SIGHUP reload failed: {:?}", e);
+            }
+        }
+
         if listener_ready {
-            let (mut stream, _addr) = self.listener.accept().context("accept failed")?;
+            // Drain every connection the kernel already has queued rather than just one: the
+            // listener is non-blocking now, so there's no risk of this looping forever, and
+            // we'd only get woken again once more connections arrive.
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, _addr)) => self.pending.push(stream),
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        eprintln!("Accept failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Service every pending connection poll found readable; anything not yet ready (still
+        // sending its request) stays queued for a future run_one call instead of being handled
+        // here and blocking everything behind it.
+        let pending = std::mem::take(&mut self.pending);
+        let mut still_pending = Vec::with_capacity(pending.len());
+        for (mut stream, ready) in pending.into_iter().zip(pending_ready) {
+            if !ready {
+                still_pending.push(stream);
+                continue;
+            }
             if let Err(err) = self.handle(&mut stream) {
                 eprintln!("Error handling request: {:?}", err);
-                let err_msg = format!("ERROR: {}\n", err);
-                let _ = stream.write_all(err_msg.as_bytes());
+                // An error message can itself contain embedded newlines (e.g. from an underlying
+                // error's own text), so encode it as a JSON string rather than splicing it
+                // into the frame raw; a client that does no more than `resp.trim()` still gets a
+                // single well-formed line out of it.
+                let err_msg = format!("ERROR: {}", json::stringify(err.to_string()));
+                let _ = write_frame(&mut stream, err_msg.as_bytes(), &[]);
             }
         }
+        self.pending = still_pending;
+
         Ok(true)
     }
 
     fn handle(&mut self, stream: &mut UnixStream) -> Result<()> {
-        let mut buf = [0u8; 1024];
-        let n = stream.read(&mut buf).context("Failed to read request")?;
-        if n == 0 {
-            // Client closed connection; just continue
+        self.check_peer_uid(stream)?;
+
+        // Every request is a single length-framed message, so a caller can optionally attach
+        // fds (e.g. TAKE3's stdin/stdout/stderr) alongside the request line; commands that don't
+        // pass any just get back an empty fd list.
+        let (req_bytes, req_fds) = read_frame(stream).context("Failed to read request")?;
+        if req_bytes.is_empty() {
+            // A zero-length frame carries nothing actionable; just continue.
             return Ok(());
         }
-        let req = String::from_utf8_lossy(&buf[..n]);
+        let req = String::from_utf8_lossy(&req_bytes).into_owned();
         eprintln!("Received request: {:?}", req);
 
-        if req.starts_with("INIT ") {
-            // Update prelude
-            let prelude = req.strip_prefix("INIT ").unwrap();
-            self.prelude_code = Some(prelude.to_string());
+        if let Some(payload) = req.strip_prefix("INIT ") {
+            // Payload is a JSON object carrying the prelude text and the command spec together,
+            // since the spec's argv/env/cwd can't be packed into a plain string the way the
+            // prelude alone used to be.
+            let parsed = json::parse(payload).context("invalid INIT payload")?;
+            let prelude = parsed["prelude"].as_str().map(str::to_string);
+            let spec = if parsed["spec"].is_null() {
+                CommandSpec::default()
+            } else {
+                CommandSpec::from_json(&parsed["spec"])?
+            };
 
-            // Kill current interpreter (if present)
-            if let Some(interp) = &self.current_interpreter.take() {
-                self.supervisor.kill(interp.id())?;
-            }
+            // Pooled interpreters were all forked with the old prelude/spec baked in, so drain
+            // them before switching and re-priming the pool.
+            self.supervisor.drain_pool()?;
+            self.prelude_code = prelude;
+            self.command_spec = spec;
+            self.supervisor
+                .refill_pool(self.prelude_code.as_deref(), &self.command_spec)?;
 
-            // Start new interpreter
-            self.ensure_interpreter()?;
             let response = "OK";
             eprintln!("Responding: {:?}", response);
-            stream
-                .write_all(response.as_bytes())
-                .context("Failed to write response")?;
+            write_frame(stream, response.as_bytes(), &[]).context("Failed to write response")?;
         } else if req == "TAKE" {
-            // Take the interpreter and return it
-            let interp = self
-                .current_interpreter
-                .as_mut()
-                .context("no interpreter")?;
+            // Hand out a ready interpreter from the pool; this is the hot path, so respond to
+            // the caller before paying the fork cost to top the pool back up.
+            let mut interp = self
+                .supervisor
+                .take_from_pool(self.prelude_code.as_deref(), &self.command_spec)?;
             interp.unsupervise()?;
             let (msg, fds) = interp.to_raw();
-            stream
-                .send_with_fd(&msg, &fds)
-                .context("take send_with_fds failed")?;
-            // Purposefully keep the reference until _after_ it's successfully sent to cli
-            self.current_interpreter = None;
-
-            // Spawn a new interpreter for next request
-            self.ensure_interpreter()?;
+            write_frame(stream, &msg, &fds).context("take write_frame failed")?;
+            // Purposefully keep the interpreter alive until _after_ it's successfully sent.
+            drop(interp);
+            self.supervisor
+                .refill_pool(self.prelude_code.as_deref(), &self.command_spec)?;
+        } else if let Some(n_str) = req.strip_prefix("POOL ") {
+            // Resize the warm pool and bring it back up to the new size.
+            let n = usize::from_str(n_str.trim()).context("invalid POOL size")?;
+            self.supervisor
+                .set_pool_size(n, self.prelude_code.as_deref(), &self.command_spec)?;
+            write_frame(stream, b"OK", &[]).context("Failed to write response")?;
+        } else if req == "POOLSIZE" {
+            let response = format!("OK {}", self.supervisor.pool_depth());
+            eprintln!("Responding: {:?}", response);
+            write_frame(stream, response.as_bytes(), &[]).context("Failed to write response")?;
+        } else if req == "STATUS" {
+            // Introspection for an operator or monitoring client: pool depth, every known
+            // child's pid/uptime or exit code, and enough about the active prelude to tell
+            // whether two servers are running the same one without shipping its full text back.
+            let prelude = match self.prelude_code.as_deref() {
+                Some(p) => {
+                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                    p.hash(&mut hasher);
+                    json::object! { len: p.len(), hash: format!("{:016x}", hasher.finish()) }
+                }
+                None => json::Null,
+            };
+            let status = json::object! {
+                pool_depth: self.supervisor.pool_depth(),
+                prelude: prelude,
+                children: self.supervisor.child_statuses(),
+            };
+            let response = format!("OK {}", json::stringify(status));
+            eprintln!("Responding: {:?}", response);
+            write_frame(stream, response.as_bytes(), &[]).context("Failed to write response")?;
         } else if req.starts_with("EXITCODE ") {
-            // Return exit code from supervisor
+            // Non-blocking: a still-running child's EXITCODE is answered later, from
+            // `answer_pending_exitcode` once SIGCHLD reaps it, rather than blocking this
+            // single-threaded event loop on a waitpid for one child while every other
+            // connection waits behind it.
             let id_str = req.strip_prefix("EXITCODE ").unwrap();
             let child_id = ChildId::from_str(id_str.trim())?;
-            let exit_code = self.supervisor.get_exit_code(child_id)?;
-            let response = format!("OK {}", exit_code);
-            eprintln!("Responding: {:?}", response);
-            stream
-                .write_all(response.as_bytes())
-                .context("Failed to write exit code response")?;
+            match self.supervisor.try_exit_code(child_id) {
+                Some(exit_code) => {
+                    let response = format!("OK {}", exit_code);
+                    eprintln!("Responding: {:?}", response);
+                    write_frame(stream, response.as_bytes(), &[])
+                        .context("Failed to write exit code response")?;
+                }
+                None => {
+                    if !self.supervisor.is_known_child(child_id.id) {
+                        bail!("unknown child {}", child_id);
+                    }
+                    let waiter = stream
+                        .try_clone()
+                        .context("Failed to clone stream for deferred EXITCODE")?;
+                    self.pending_exitcode.entry(child_id).or_default().push(waiter);
+                }
+            }
+        } else if req == "UPGRADE" {
+            self.handle_upgrade(stream)?;
+        } else if let Some(pty_flag) = req.strip_prefix("TAKE3 ") {
+            // Spawn an interpreter wired directly to the 3 caller-supplied stdio fds, bypassing
+            // the (PTY-only) pool entirely.
+            if req_fds.len() != 3 {
+                bail!("TAKE3 requires exactly 3 fds, got {}", req_fds.len());
+            }
+            let fd_arr: [RawFd; 3] = [req_fds[0], req_fds[1], req_fds[2]];
+            let acquire_tty = match pty_flag.trim() {
+                "1" => true,
+                "0" => false,
+                other => bail!("invalid TAKE3 pty flag {:?}", other),
+            };
+            let (child_id, control_fd) = self.supervisor.spawn_three_stream(
+                self.prelude_code.as_deref(),
+                fd_arr,
+                acquire_tty,
+                &self.command_spec,
+            )?;
+            // The child has its own dup'd copies now; drop ours so the long-lived server
+            // doesn't accumulate the caller's stdio fds across repeated TAKE3 calls.
+            for fd in fd_arr {
+                let _ = close(fd);
+            }
+            let msg = child_id.to_string().into_bytes();
+            write_frame(stream, &msg, &[control_fd.as_raw_fd()])
+                .context("take3 write_frame failed")?;
         } else {
             bail!("Unknown command '{}'", req)
         }
 
         Ok(())
     }
+
+    // Re-exec the current binary in place, handing the bound listener and warm pool across the
+    // gap so in-flight and pooled interpreters survive a server upgrade or prelude reload.
+    // Shared by the explicit UPGRADE request and a SIGHUP-triggered reload; `ack` runs after the
+    // state is durably written and FD_CLOEXEC cleared but before the exec, so a caller can send
+    // its own response (or do nothing, for SIGHUP) only once those steps have succeeded. A
+    // failure after `ack` (resolving the executable, or execv itself) still returns an error
+    // after the response has gone out.
+    fn upgrade_in_place(&mut self, ack: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.supervisor
+            .write_upgrade_state(
+                UPGRADE_STATE_PATH,
+                self.prelude_code.as_deref(),
+                &self.command_spec,
+            )
+            .context("Failed to write upgrade state")?;
+
+        clear_cloexec(self.listener.as_raw_fd())
+            .context("Failed to clear FD_CLOEXEC on listener")?;
+        for fd in self.supervisor.pool_raw_fds() {
+            clear_cloexec(fd).context("Failed to clear FD_CLOEXEC on pooled interpreter fd")?;
+        }
+
+        ack()?;
+
+        env::set_var(UPGRADE_LISTENER_FD_ENV, self.listener.as_raw_fd().to_string());
+        env::set_var(UPGRADE_STATE_ENV, UPGRADE_STATE_PATH);
+
+        let exe = env::current_exe().context("Failed to resolve current executable")?;
+        let exe_cstr = CString::new(exe.to_string_lossy().into_owned())
+            .context("executable path is not a valid C string")?;
+        let result = execv(&exe_cstr, &[exe_cstr.clone()]);
+
+        // Only reached if execv failed outright; a successful call replaces this process and
+        // never returns. Clean up so a later, unrelated restart doesn't pick up stale env vars.
+        env::remove_var(UPGRADE_LISTENER_FD_ENV);
+        env::remove_var(UPGRADE_STATE_ENV);
+        result.context("execv failed during upgrade")?;
+        unreachable!("execv returned without error")
+    }
+
+    fn handle_upgrade(&mut self, stream: &mut UnixStream) -> Result<()> {
+        self.upgrade_in_place(|| {
+            write_frame(stream, b"OK", &[]).context("Failed to write response")
+        })
+    }
+
+    // Answer any EXITCODE requests parked in `pending_exitcode` whose child has an exit code
+    // recorded now (i.e. SIGCHLD just reaped it). Called after every `handle_sigchld` so a
+    // parked connection is answered as soon as possible rather than polled for.
+    fn answer_pending_exitcode(&mut self) {
+        let ready: Vec<ChildId> = self
+            .pending_exitcode
+            .keys()
+            .filter(|id| self.supervisor.try_exit_code(**id).is_some())
+            .copied()
+            .collect();
+        for child_id in ready {
+            let exit_code = self.supervisor.try_exit_code(child_id).unwrap();
+            let response = format!("OK {}", exit_code);
+            for mut waiter in self.pending_exitcode.remove(&child_id).unwrap_or_default() {
+                eprintln!("Responding: {:?}", response);
+                let _ = write_frame(&mut waiter, response.as_bytes(), &[]);
+            }
+        }
+    }
+}
+
+fn clear_cloexec(fd: RawFd) -> Result<()> {
+    fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty())).context("fcntl F_SETFD failed")?;
+    Ok(())
+}
+
+// Ask a running daemon to perform the same graceful, fd-preserving reload that SIGHUP and the
+// UPGRADE request trigger (see `upgrade_in_place`), so `--restart` no longer has to close the
+// listening socket or drop the warm pool to bring up a fresh process.
+fn send_upgrade_request() -> Result<()> {
+    let mut stream = UnixStream::connect(SOCKET_PATH).context("Failed to connect to server")?;
+    write_frame(&mut stream, b"UPGRADE", &[]).context("Failed to send UPGRADE request")?;
+    let (payload, _fds) = read_frame(&mut stream).context("Failed to read UPGRADE response")?;
+    let resp = String::from_utf8_lossy(&payload);
+    if resp.trim() == "OK" {
+        Ok(())
+    } else {
+        bail!("UPGRADE failed: {}", resp)
+    }
 }
 
 pub fn restart() -> Result<()> {
     if let Some(pid) = PidFileGuard::test(PIDFILE_PATH)? {
-        kill_with_timeout(pid, Duration::from_secs(2))?;
-        // Attempt to remove the PID file just in case. Errors are ignored.
-        let _ = std::fs::remove_file(PIDFILE_PATH);
+        // Prefer the graceful re-exec handoff: it keeps the socket bound (no ECONNREFUSED
+        // window for clients racing the restart) and carries the warm pool across instead of
+        // forcing every caller to pay a fresh cold start. Only fall back to a hard kill if the
+        // running daemon doesn't answer at all, e.g. because it's wedged.
+        if let Err(e) = send_upgrade_request() {
+            eprintln!("Graceful restart failed ({e:#}); falling back to a hard restart");
+            kill_with_timeout(pid, Duration::from_secs(2))?;
+            // Attempt to remove the PID file just in case. Errors are ignored.
+            let _ = std::fs::remove_file(PIDFILE_PATH);
+            return ensure();
+        }
+        return Ok(());
     }
     ensure()
 }
@@ -251,3 +612,45 @@ fn serve() -> Result<()> {
     server.run()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_peer_uid_allowed_own_uid() {
+        assert!(is_peer_uid_allowed(1000, 1000, &[]));
+    }
+
+    #[test]
+    fn test_is_peer_uid_allowed_on_allowlist() {
+        assert!(is_peer_uid_allowed(1001, 1000, &[1001, 1002]));
+    }
+
+    #[test]
+    fn test_is_peer_uid_allowed_rejects_mismatch() {
+        assert!(!is_peer_uid_allowed(1001, 1000, &[]));
+        assert!(!is_peer_uid_allowed(1003, 1000, &[1001, 1002]));
+    }
+
+    #[test]
+    fn test_parse_allowed_uids_str_empty() {
+        assert_eq!(parse_allowed_uids_str("").unwrap(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_parse_allowed_uids_str_multiple_with_whitespace() {
+        assert_eq!(parse_allowed_uids_str(" 1000, 1001 ,1002").unwrap(), vec![1000, 1001, 1002]);
+    }
+
+    #[test]
+    fn test_parse_allowed_uids_str_skips_empty_entries() {
+        assert_eq!(parse_allowed_uids_str("1000,,1001,").unwrap(), vec![1000, 1001]);
+    }
+
+    #[test]
+    fn test_parse_allowed_uids_str_rejects_malformed_entry() {
+        assert!(parse_allowed_uids_str("1000,not-a-uid").is_err());
+        assert!(parse_allowed_uids_str("-1").is_err());
+    }
+}
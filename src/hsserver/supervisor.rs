@@ -1,29 +1,140 @@
 use anyhow::{bail, Context, Result};
-use nix::fcntl::{open, OFlag};
-use std::os::unix::net::UnixStream;
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
 use std::fs::File;
 use nix::libc;
 use nix::pty::{grantpt, posix_openpt, ptsname, unlockpt};
+use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
 use nix::sys::stat::Mode;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use nix::unistd::{close, dup2, execvp, fork, getpid, setsid, tcsetpgrp, ForkResult};
-use std::collections::HashMap;
+use nix::unistd::{chdir, close, dup2, execve, fork, getpid, pipe, setsid, tcsetpgrp, ForkResult};
+use std::collections::{HashMap, VecDeque};
 use std::ffi::CString;
-use std::os::fd::{AsRawFd, IntoRawFd, FromRawFd};
+use std::io::Read;
+use std::os::fd::{AsRawFd, IntoRawFd, FromRawFd, OwnedFd, RawFd};
 use std::time::{Duration, Instant};
+use crate::hsserver::daemon::process_is_alive;
 use crate::interpreter::{ChildId, Interpreter};
 
 const SCRIPT: &str = include_str!("../pyhotstart.py");
 const SCRIPT_PATH: &str = "/tmp/pyhotstart.py";
 
+// How many trailing bytes of a crashed interpreter's stderr `child_exit` keeps around to log,
+// so a traceback printed right before exit doesn't vanish along with the PTY the shell prompt
+// was sharing.
+const STDERR_TAIL_CAP: usize = 4096;
+
+// Jobserver-style cap on how many interpreters a single `refill_pool` call will fork before
+// returning; see `refill_pool`'s doc comment.
+const MAX_SPAWNS_PER_REFILL: usize = 2;
+
 // For TIOCSCTTY
 nix::ioctl_write_int_bad!(ioctl_set_ctty, libc::TIOCSCTTY);
 
+// Full specification of how to exec a spawned interpreter's process: which binary to run, what
+// extra argv to pass it, what environment it sees, and what directory it starts in. `Default`
+// reproduces the original hardcoded behavior (plain `python3`, the server's own environment,
+// the server's own cwd).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    // Resolved the same way a shell would: a bare name (no '/') is searched for on PATH, so
+    // alternate interpreters like `pypy3` or a virtualenv's `python` work without a full path.
+    pub executable: String,
+    // Extra argv entries, placed between the executable and the generated interpreter script's
+    // path by default. A literal "{script}" entry is replaced with that path instead, so a
+    // wrapper whose own syntax needs the script path somewhere other than last can still be
+    // expressed, e.g. `args: ["--", "{script}"]`.
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    // If true, `env` is the entire environment handed to the child. If false, `env` is merged
+    // on top of the server's own environment as overrides.
+    pub clear_env: bool,
+    pub cwd: Option<String>,
+}
+
+impl Default for CommandSpec {
+    fn default() -> Self {
+        CommandSpec {
+            executable: "python3".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            clear_env: false,
+            cwd: None,
+        }
+    }
+}
+
+impl CommandSpec {
+    // Encode as a JSON object, e.g. for embedding in the INIT request or the upgrade-state file.
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            executable: self.executable.clone(),
+            args: self.args.clone(),
+            env: self.env.clone(),
+            clear_env: self.clear_env,
+            cwd: self.cwd.clone(),
+        }
+    }
+
+    // Inverse of `to_json`; fields missing from `v` fall back to `CommandSpec::default()`.
+    pub fn from_json(v: &json::JsonValue) -> Result<CommandSpec> {
+        let default = CommandSpec::default();
+        let executable = v["executable"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or(default.executable);
+        let args = if v["args"].is_null() {
+            Vec::new()
+        } else {
+            v["args"]
+                .members()
+                .map(|m| {
+                    m.as_str()
+                        .map(str::to_string)
+                        .context("invalid args entry")
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        let env = if v["env"].is_null() {
+            HashMap::new()
+        } else {
+            v["env"]
+                .entries()
+                .map(|(k, val)| {
+                    let s = val.as_str().context("invalid env value")?;
+                    Ok((k.to_string(), s.to_string()))
+                })
+                .collect::<Result<HashMap<_, _>>>()?
+        };
+        let clear_env = v["clear_env"].as_bool().unwrap_or(default.clear_env);
+        let cwd = v["cwd"].as_str().map(str::to_string);
+        Ok(CommandSpec {
+            executable,
+            args,
+            env,
+            clear_env,
+            cwd,
+        })
+    }
+}
+
 pub struct Supervisor {
     next_child_id: u32,
     running_children: HashMap<Pid, u32>,
+    // Spawn time of each still-running child, keyed by child id rather than pid so a lookup
+    // keeps working after the pid itself has been recycled by the kernel. Used only for the
+    // STATUS command's uptime field; not persisted across an upgrade (see `from_upgrade_state`),
+    // so a child's uptime simply resets to "just now" across a re-exec.
+    spawn_times: HashMap<u32, Instant>,
+    // Read end of each still-running child's dedicated stderr pipe (see `spawn`), keyed by
+    // child id. `child_exit` drains whatever's buffered here and logs it on a non-zero exit, so
+    // a traceback isn't lost along with the PTY slave stdout/stdin were also sharing. Like
+    // `spawn_times`, not persisted across an upgrade: a child that crashes in the exec gap of a
+    // re-exec just loses its captured tail.
+    stderr_pipes: HashMap<u32, File>,
     exit_info: ExitInfoRecord,
+    pool: VecDeque<Interpreter>,
+    pool_size: usize,
 }
 
 impl Supervisor {
@@ -31,21 +142,273 @@ impl Supervisor {
         Supervisor {
             next_child_id: 1,
             running_children: HashMap::new(),
+            spawn_times: HashMap::new(),
+            stderr_pipes: HashMap::new(),
             exit_info: ExitInfoRecord::new(128),
+            pool: VecDeque::new(),
+            pool_size: 1,
         }
     }
 
     pub fn spawn_interpreter(
         &mut self,
         prelude_code: Option<&str>,
+        spec: &CommandSpec,
     ) -> Result<Interpreter> {
-        let interpreter = spawn(self.next_child_id, prelude_code)?;
+        let (interpreter, stderr_pipe) = spawn(self.next_child_id, prelude_code, spec)?;
         let child_id = self.next_child_id;
         self.next_child_id += 1;
         self.running_children.insert(interpreter.id().pid, child_id);
+        self.spawn_times.insert(child_id, Instant::now());
+        self.stderr_pipes.insert(child_id, stderr_pipe);
         Ok(interpreter)
     }
 
+    pub fn pool_depth(&self) -> usize {
+        self.pool.len()
+    }
+
+    // Fork an interpreter wired directly to caller-supplied stdin/stdout/stderr fds instead of
+    // the usual shared PTY slave, so stdout and stderr reach the caller independently (e.g. to
+    // parse structured logs off stderr while stdout carries program output). Bypasses the pool
+    // entirely: there's no single pty_master_fd to hand back and warm, so these are spawned
+    // fresh on demand. Returns the new child's id and its control socket; the caller owns
+    // `fds` and should close its copies once the child has dup'd them.
+    pub fn spawn_three_stream(
+        &mut self,
+        prelude_code: Option<&str>,
+        fds: [RawFd; 3],
+        acquire_tty: bool,
+        spec: &CommandSpec,
+    ) -> Result<(ChildId, OwnedFd)> {
+        let (pid, control_w) = spawn_three_stream(prelude_code, fds, acquire_tty, spec)?;
+        let child_id = ChildId::new(self.next_child_id, pid);
+        self.next_child_id += 1;
+        self.running_children.insert(pid, child_id.id);
+        self.spawn_times.insert(child_id.id, Instant::now());
+        Ok((child_id, control_w))
+    }
+
+    // Set the number of pre-forked interpreters to keep warm and top the pool back up (or
+    // trim it down) to match. Excess members are killed outright rather than left to expire.
+    pub fn set_pool_size(
+        &mut self,
+        n: usize,
+        prelude_code: Option<&str>,
+        spec: &CommandSpec,
+    ) -> Result<()> {
+        self.pool_size = n;
+        while self.pool.len() > self.pool_size {
+            if let Some(interp) = self.pool.pop_back() {
+                self.kill(interp.id())?;
+            }
+        }
+        self.refill_pool(prelude_code, spec)
+    }
+
+    // Top the pool back up to `pool_size` by forking fresh interpreters past the same prelude
+    // and command spec, but fork at most `MAX_SPAWNS_PER_REFILL` of them before returning. This
+    // is the jobserver pattern build runners use to cap concurrent work, adapted to a
+    // single-threaded fork server: there's no pool of worker threads to hand tokens to, so the
+    // "token count" instead bounds how many children one call is allowed to fork before giving
+    // the rest of the server a turn. A `POOL <n>` jump of more than a few slots therefore
+    // catches up gradually across the next several calls (each one triggered by a subsequent
+    // TAKE) rather than forking the whole deficit in one burst that stalls the single accept
+    // loop. Still a no-op once the pool is full.
+    pub fn refill_pool(&mut self, prelude_code: Option<&str>, spec: &CommandSpec) -> Result<()> {
+        let mut spawned = 0;
+        while self.pool.len() < self.pool_size && spawned < MAX_SPAWNS_PER_REFILL {
+            let interp = self.spawn_interpreter(prelude_code, spec)?;
+            self.pool.push_back(interp);
+            spawned += 1;
+        }
+        Ok(())
+    }
+
+    // Hand a ready interpreter off the front of the pool, skipping over (and evicting) any
+    // pooled members whose process has already died, as detected via the pidfd liveness check.
+    // Deliberately does *not* refill the pool itself: that fork is the one part of a TAKE that
+    // isn't O(1), so callers should respond to the client first and call `refill_pool`
+    // afterwards, off the hot path. Falls back to forking fresh if the pool couldn't keep up
+    // with demand.
+    pub fn take_from_pool(
+        &mut self,
+        prelude_code: Option<&str>,
+        spec: &CommandSpec,
+    ) -> Result<Interpreter> {
+        while let Some(interp) = self.pool.pop_front() {
+            if process_is_alive(interp.id().pid)? {
+                return Ok(interp);
+            }
+            eprintln!("Evicting dead pooled interpreter {}", interp.id());
+        }
+        self.spawn_interpreter(prelude_code, spec)
+    }
+
+    // Kill every pooled interpreter. Used before re-priming the pool with a new prelude, and
+    // implicitly covered again by `Drop` on daemon shutdown.
+    pub fn drain_pool(&mut self) -> Result<()> {
+        while let Some(interp) = self.pool.pop_front() {
+            self.kill(interp.id())?;
+        }
+        Ok(())
+    }
+
+    // Raw fds of every pooled interpreter's control socket and PTY master. The caller clears
+    // FD_CLOEXEC on these before a server re-exec so the pool survives execve() intact.
+    pub fn pool_raw_fds(&self) -> Vec<RawFd> {
+        self.pool
+            .iter()
+            .flat_map(|interp| {
+                let (control_fd, pty_fd) = interp.raw_fds();
+                [control_fd, pty_fd]
+            })
+            .collect()
+    }
+
+    // Serialize everything needed to rebuild this Supervisor (and the prelude that's active)
+    // after a re-exec: next_child_id, the running-children map, the exit-info ring, the pool
+    // size, and the pool members themselves (by raw fd number, since their FD_CLOEXEC flag was
+    // already cleared so they survive execve() in place). One directive per line, matching the
+    // request-line style `handle()` already parses elsewhere in this server.
+    pub fn write_upgrade_state(
+        &self,
+        path: &str,
+        prelude_code: Option<&str>,
+        command_spec: &CommandSpec,
+    ) -> Result<()> {
+        let mut out = String::new();
+        out.push_str(&format!("NEXT_CHILD_ID {}\n", self.next_child_id));
+        out.push_str(&format!("POOL_SIZE {}\n", self.pool_size));
+        if let Some(prelude) = prelude_code {
+            out.push_str(&format!("PRELUDE {}\n", json::stringify(prelude)));
+        }
+        out.push_str(&format!("SPEC {}\n", json::stringify(command_spec.to_json())));
+        for (pid, child_id) in &self.running_children {
+            out.push_str(&format!("RUNNING {} {}\n", pid.as_raw(), child_id));
+        }
+        for (child_id, exit_code) in self.exit_info.entries() {
+            out.push_str(&format!("EXIT {} {}\n", child_id, exit_code));
+        }
+        for interp in &self.pool {
+            let (control_fd, pty_fd) = interp.raw_fds();
+            out.push_str(&format!("POOL {} {} {}\n", interp.id(), control_fd, pty_fd));
+        }
+        std::fs::write(path, out)
+            .with_context(|| format!("Failed to write upgrade state to {}", path))
+    }
+
+    // Reconstruct a Supervisor (plus the prelude that was active) from the state a sibling
+    // process wrote via `write_upgrade_state` just before re-exec'ing into us. Pool members are
+    // rebuilt straight from their raw fd numbers, which stayed open across execve() once their
+    // FD_CLOEXEC flag was cleared.
+    pub fn from_upgrade_state(path: &str) -> Result<(Option<String>, CommandSpec, Supervisor)> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read upgrade state from {}", path))?;
+
+        let mut next_child_id = 1u32;
+        let mut pool_size = 1usize;
+        let mut prelude_code = None;
+        let mut command_spec = CommandSpec::default();
+        let mut running_children = HashMap::new();
+        let mut exit_info = ExitInfoRecord::new(128);
+        let mut pool = VecDeque::new();
+
+        for line in contents.lines() {
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("NEXT_CHILD_ID") => {
+                    next_child_id = parts
+                        .next()
+                        .context("NEXT_CHILD_ID missing value")?
+                        .parse()
+                        .context("invalid NEXT_CHILD_ID")?;
+                }
+                Some("POOL_SIZE") => {
+                    pool_size = parts
+                        .next()
+                        .context("POOL_SIZE missing value")?
+                        .parse()
+                        .context("invalid POOL_SIZE")?;
+                }
+                Some("PRELUDE") => {
+                    let rest = line.strip_prefix("PRELUDE ").unwrap_or("");
+                    let parsed = json::parse(rest).context("invalid PRELUDE json")?;
+                    let s = parsed.as_str().context("PRELUDE is not a string")?;
+                    prelude_code = Some(s.to_string());
+                }
+                Some("SPEC") => {
+                    let rest = line.strip_prefix("SPEC ").unwrap_or("");
+                    let parsed = json::parse(rest).context("invalid SPEC json")?;
+                    command_spec = CommandSpec::from_json(&parsed)?;
+                }
+                Some("RUNNING") => {
+                    let pid: i32 = parts
+                        .next()
+                        .context("RUNNING missing pid")?
+                        .parse()
+                        .context("invalid RUNNING pid")?;
+                    let child_id: u32 = parts
+                        .next()
+                        .context("RUNNING missing child_id")?
+                        .parse()
+                        .context("invalid RUNNING child_id")?;
+                    running_children.insert(Pid::from_raw(pid), child_id);
+                }
+                Some("EXIT") => {
+                    let child_id: u32 = parts
+                        .next()
+                        .context("EXIT missing child_id")?
+                        .parse()
+                        .context("invalid EXIT child_id")?;
+                    let exit_code: i32 = parts
+                        .next()
+                        .context("EXIT missing exit_code")?
+                        .parse()
+                        .context("invalid EXIT exit_code")?;
+                    exit_info.set(child_id, exit_code);
+                }
+                Some("POOL") => {
+                    let id_str = parts.next().context("POOL missing id")?;
+                    let control_fd: RawFd = parts
+                        .next()
+                        .context("POOL missing control_fd")?
+                        .parse()
+                        .context("invalid POOL control_fd")?;
+                    let pty_fd: RawFd = parts
+                        .next()
+                        .context("POOL missing pty_fd")?
+                        .parse()
+                        .context("invalid POOL pty_fd")?;
+                    let interp =
+                        unsafe { Interpreter::from_raw(id_str.as_bytes(), &[control_fd, pty_fd])? };
+                    pool.push_back(interp);
+                }
+                Some(other) => bail!("unknown upgrade state directive {:?}", other),
+                None => {}
+            }
+        }
+
+        let spawn_times: HashMap<u32, Instant> = running_children
+            .values()
+            .map(|&id| (id, Instant::now()))
+            .collect();
+
+        Ok((
+            prelude_code,
+            command_spec,
+            Supervisor {
+                next_child_id,
+                running_children,
+                spawn_times,
+                stderr_pipes: HashMap::new(),
+                exit_info,
+                pool,
+                pool_size,
+            },
+        ))
+    }
+
     pub fn get_exit_code(&mut self, child_id: ChildId) -> Result<i32> {
         // First, check if we already have the exit code recorded
         if let Some(code) = self.exit_info.get(child_id.id) {
@@ -60,6 +423,21 @@ impl Supervisor {
         bail!("could not get exit code for child {}", child_id);
     }
 
+    // Non-blocking lookup of an already-recorded exit code, never waiting on the child itself.
+    // Used by the local server's EXITCODE handler so a still-running child doesn't stall the
+    // single-threaded event loop; pair with `is_known_child` to tell "still running" apart from
+    // "never handed out".
+    pub fn try_exit_code(&self, child_id: ChildId) -> Option<i32> {
+        self.exit_info.get(child_id.id)
+    }
+
+    // Whether `id` has ever been handed out by this supervisor, whether or not it's still
+    // running. Lets a non-blocking EXITCODE caller reject a pid that was never issued instead of
+    // parking it forever waiting on a SIGCHLD that will never name it.
+    pub fn is_known_child(&self, id: u32) -> bool {
+        self.running_children.values().any(|&v| v == id) || self.exit_info.get(id).is_some()
+    }
+
     pub fn kill(&mut self, child_id: &ChildId) -> Result<i32> {
         if self.running_children.contains_key(&child_id.pid) {
             // Send SIGTERM to request graceful termination
@@ -74,6 +452,7 @@ impl Supervisor {
                 self.wait(Some(child_id.pid), Some(WaitPidFlag::WNOHANG))?;
                 if let Some(code) = self.exit_info.get(child_id.id) {
                     status = code;
+                    break;
                 } else {
                     // Not exited yet, wait a bit longer
                     std::thread::sleep(Duration::from_millis(20));
@@ -115,9 +494,57 @@ impl Supervisor {
             .running_children
             .remove(pid)
             .with_context(|| format!("unrecognized pid {}", pid))?;
+        self.spawn_times.remove(&id);
+        if let Some(mut pipe) = self.stderr_pipes.remove(&id) {
+            // The child has already exited, so its end of the pipe is closed; draining now
+            // just picks up whatever it wrote before it went down, with no risk of blocking.
+            if exit_code != 0 {
+                let tail = read_stderr_tail(&mut pipe);
+                if !tail.is_empty() {
+                    eprintln!(
+                        "interpreter {} exited with code {}; stderr tail:\n{}",
+                        id,
+                        exit_code,
+                        String::from_utf8_lossy(&tail)
+                    );
+                }
+            }
+        }
         self.exit_info.set(id, exit_code);
         Ok(())
     }
+
+    // Snapshot of every child the supervisor knows about, for the STATUS command: still-running
+    // children report their pid and uptime, and the most recent exits (per `exit_info`'s ring
+    // buffer) report their exit code. Pooled interpreters are included since they're also
+    // tracked in `running_children` from the moment they're forked.
+    pub fn child_statuses(&self) -> Vec<json::JsonValue> {
+        let mut statuses: Vec<json::JsonValue> = self
+            .running_children
+            .iter()
+            .map(|(pid, id)| {
+                let uptime_secs = self
+                    .spawn_times
+                    .get(id)
+                    .map(|t| t.elapsed().as_secs())
+                    .unwrap_or(0);
+                json::object! {
+                    id: *id,
+                    pid: pid.as_raw(),
+                    state: "alive",
+                    uptime_secs: uptime_secs,
+                }
+            })
+            .collect();
+        for (id, exit_code) in self.exit_info.entries() {
+            statuses.push(json::object! {
+                id: id,
+                state: "exited",
+                exit_code: exit_code,
+            });
+        }
+        statuses
+    }
 }
 
 impl Drop for Supervisor {
@@ -175,9 +602,20 @@ impl ExitInfoRecord {
             .find(|&(_, &id)| id == child_id)
             .map(|(i, _)| self.exit_codes[i])
     }
+
+    // All (child_id, exit_code) pairs currently held, oldest first, so replaying them through
+    // `set` on a fresh ring reproduces the exact same state.
+    fn entries(&self) -> Vec<(u32, i32)> {
+        (0..self.count)
+            .map(|i| {
+                let idx = (self.start + i) % self.limit;
+                (self.child_ids[idx], self.exit_codes[idx])
+            })
+            .collect()
+    }
 }
 
-fn spawn(id: u32, prelude_code: Option<&str>) -> Result<Interpreter> {
+fn spawn(id: u32, prelude_code: Option<&str>, spec: &CommandSpec) -> Result<(Interpreter, File)> {
     // Set up dedicated PTY for interpreter's stdio
     let master_fd =
         posix_openpt(OFlag::O_RDWR | OFlag::O_CLOEXEC).context("Failed to open PTY master")?;
@@ -187,21 +625,50 @@ fn spawn(id: u32, prelude_code: Option<&str>) -> Result<Interpreter> {
     let slave_name = unsafe { ptsname(&master_fd) }.context("Failed to get PTY slave name")?;
     let slave_path: &str = slave_name.as_ref();
 
-    // Create a separate stream for sending instructions to the running interpreter.
-    let (control_r, control_w) = UnixStream::pair().context("Failed to create control socket pair")?;
+    // Create a seqpacket socket pair for sending instructions to the running interpreter:
+    // SOCK_SEQPACKET preserves message boundaries, so a single send()/recv() maps to exactly
+    // one instruction, with no newline framing or quoting needed for arbitrary/multi-line code.
+    let (control_r, control_w) = socketpair(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        None,
+        SockFlag::empty(),
+    )
+    .context("Failed to create control socket pair")?;
     debug_assert!(control_r.as_raw_fd() > 3, "control_r fd is too low");
 
+    // A dedicated pipe for the child's stderr instead of sharing the PTY slave with stdin/
+    // stdout: a crash traceback printed right as the interpreter exits can otherwise race the
+    // PTY teardown and never reach the client, since the pool/TAKE protocol only proxies the
+    // PTY to whichever caller currently holds it, not the server itself.
+    let (stderr_r, stderr_w) = pipe().context("Failed to create stderr pipe")?;
+
+    // Built before fork(): allocating CStrings in the child of a fork from a multi-threaded
+    // parent risks deadlocking on a heap lock another thread held at fork time.
+    let (executable, argv, envp, cwd) = prepare_exec(prelude_code, spec)?;
+
     match unsafe { fork() }.context("fork failed")? {
-        ForkResult::Parent { child } => Ok(Interpreter::new(
-            ChildId::new(id, child),
-            control_w,
-            unsafe { File::from_raw_fd(master_fd.into_raw_fd()) },
-        )),
+        ForkResult::Parent { child } => {
+            // Only the child writes to stderr_w; drop our copy so EOF on stderr_r actually
+            // fires once the child exits instead of waiting on this lingering reference too.
+            drop(stderr_w);
+            let stderr_pipe: File = stderr_r.into();
+            fcntl(stderr_pipe.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+                .context("Failed to set stderr pipe non-blocking")?;
+            Ok((
+                Interpreter::new(
+                    ChildId::new(id, child),
+                    control_w,
+                    unsafe { File::from_raw_fd(master_fd.into_raw_fd()) },
+                ),
+                stderr_pipe,
+            ))
+        }
         ForkResult::Child => {
             // Child: setsid, set controlling TTY
             setsid().expect("setsid failed");
 
-            // Attach tty slave device to stdin, stdout, stderr
+            // Attach tty slave device to stdin, stdout; stderr goes to the dedicated pipe above.
             {
                 // Open slave fd
                 let slave_fd = open(
@@ -211,13 +678,17 @@ fn spawn(id: u32, prelude_code: Option<&str>) -> Result<Interpreter> {
                 )
                 .expect("Failed to open pty slave");
 
-                // Assign to stdin, stdout, stderr
+                // Assign to stdin, stdout
                 dup2(slave_fd, 0).expect("dup2 stdin failed");
                 dup2(slave_fd, 1).expect("dup2 stdout failed");
-                dup2(slave_fd, 2).expect("dup2 stderr failed");
+                dup2(stderr_w.as_raw_fd(), 2).expect("dup2 stderr failed");
                 if slave_fd > 2 {
                     close(slave_fd).expect("failed to close pty slave fd");
                 }
+                close(stderr_r.as_raw_fd()).expect("failed to close stderr pipe read end");
+                if stderr_w.as_raw_fd() > 2 {
+                    close(stderr_w.as_raw_fd()).expect("failed to close stderr pipe write end");
+                }
             }
 
             // Dup control_r fd to 3 so that it survives exec and can be used by interpreter
@@ -230,14 +701,172 @@ fn spawn(id: u32, prelude_code: Option<&str>) -> Result<Interpreter> {
             let pid = getpid();
             tcsetpgrp(std::io::stdin(), pid).expect("tcsetpgrp failed");
 
-            // Prepare python command
-            let script_with_prelude = SCRIPT.replace("# prelude", prelude_code.unwrap_or(""));
-            std::fs::write(SCRIPT_PATH, script_with_prelude)
-                .context("Failed to write to temp file")?;
-            let python = CString::new("python3").unwrap();
-            let args = [python.clone(), CString::new(SCRIPT_PATH).unwrap()];
-            execvp(&python, &args).expect("execvp failed");
-            unreachable!()
+            exec_prepared(executable, argv, envp, cwd)
+        }
+    }
+}
+
+// Drain whatever's buffered in a crashed interpreter's stderr pipe, keeping only the last
+// `STDERR_TAIL_CAP` bytes. Non-blocking: `child_exit` only calls this after the child has already
+// been reaped, so a `WouldBlock` here just means there's nothing more to read, not that a writer
+// is still active.
+fn read_stderr_tail(pipe: &mut File) -> Vec<u8> {
+    let mut buf = [0u8; 1024];
+    let mut out = Vec::new();
+    loop {
+        match pipe.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                out.extend_from_slice(&buf[..n]);
+                if out.len() > STDERR_TAIL_CAP {
+                    out.drain(..out.len() - STDERR_TAIL_CAP);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+            Err(_) => break,
+        }
+    }
+    out
+}
+
+// Writes the prelude-expanded interpreter script to SCRIPT_PATH and builds everything
+// `exec_prepared` needs to exec `spec` against it, all as plain owned data. Must run before
+// fork(): see the allocation-hazard note at its call sites.
+fn prepare_exec(
+    prelude_code: Option<&str>,
+    spec: &CommandSpec,
+) -> Result<(CString, Vec<CString>, Vec<CString>, Option<CString>)> {
+    let script_with_prelude = SCRIPT.replace("# prelude", prelude_code.unwrap_or(""));
+    std::fs::write(SCRIPT_PATH, script_with_prelude).context("Failed to write to temp file")?;
+
+    let mut env_map: HashMap<String, String> = if spec.clear_env {
+        HashMap::new()
+    } else {
+        std::env::vars().collect()
+    };
+    env_map.extend(spec.env.clone());
+
+    // Resolved against the environment the child will actually see, not the server's own, so
+    // an overridden PATH in `spec.env` takes effect.
+    let resolved = resolve_executable(&spec.executable, env_map.get("PATH").map(String::as_str))?;
+    let executable = CString::new(resolved).context("invalid executable path")?;
+
+    // argv[0] stays the name as given (e.g. "pypy3"), matching what execvp/execvpe would pass,
+    // even though the exec target above is the resolved full path.
+    let mut argv = vec![CString::new(spec.executable.clone()).context("invalid executable")?];
+    let script_cstr = CString::new(SCRIPT_PATH).unwrap();
+    if spec.args.iter().any(|a| a == "{script}") {
+        for arg in &spec.args {
+            if arg == "{script}" {
+                argv.push(script_cstr.clone());
+            } else {
+                argv.push(CString::new(arg.clone()).context("invalid arg")?);
+            }
+        }
+    } else {
+        for arg in &spec.args {
+            argv.push(CString::new(arg.clone()).context("invalid arg")?);
+        }
+        argv.push(script_cstr);
+    }
+
+    let envp = env_map
+        .into_iter()
+        .map(|(k, v)| CString::new(format!("{k}={v}")).context("invalid env entry"))
+        .collect::<Result<Vec<_>>>()?;
+
+    let cwd = spec
+        .cwd
+        .as_ref()
+        .map(|c| CString::new(c.clone()).context("invalid cwd"))
+        .transpose()?;
+
+    Ok((executable, argv, envp, cwd))
+}
+
+// Mimics the PATH-search half of execvpe: a bare name (no '/') is resolved against PATH-style
+// colon-separated directories, picking the first that names an existing file, so bare names like
+// "pypy3" or a virtualenv's "python" work the same way they would typed at a shell. A name
+// containing '/' (an absolute path, or e.g. "./venv/bin/python") is used exactly as given, since
+// that's how execve (and execvp) already treat it.
+fn resolve_executable(name: &str, path_env: Option<&str>) -> Result<String> {
+    if name.contains('/') {
+        return Ok(name.to_string());
+    }
+    for dir in path_env.unwrap_or_default().split(':') {
+        if dir.is_empty() {
+            continue;
+        }
+        let candidate = std::path::Path::new(dir).join(name);
+        if candidate.is_file() {
+            return Ok(candidate.to_string_lossy().into_owned());
+        }
+    }
+    bail!("executable {:?} not found on PATH", name)
+}
+
+// Child-side half of `prepare_exec`: chdir (if requested), then execve with the prepared
+// argv/envp. Shared by every spawn mode (PTY-backed pool members and three-stream spawns
+// alike); never returns.
+fn exec_prepared(
+    executable: CString,
+    argv: Vec<CString>,
+    envp: Vec<CString>,
+    cwd: Option<CString>,
+) -> ! {
+    if let Some(cwd) = cwd {
+        chdir(cwd.as_c_str()).expect("chdir failed");
+    }
+    execve(&executable, &argv, &envp).expect("execve failed");
+    unreachable!()
+}
+
+// Fork a child wired directly to `fds` (stdin, stdout, stderr) instead of a shared PTY slave.
+// `acquire_tty` is only meaningful when those fds are themselves backed by a PTY (as opposed to
+// plain pipes): it drives the same setsid/TIOCSCTTY dance `spawn` does, so the child picks up a
+// controlling terminal on the caller-supplied PTY rather than none at all.
+fn spawn_three_stream(
+    prelude_code: Option<&str>,
+    fds: [RawFd; 3],
+    acquire_tty: bool,
+    spec: &CommandSpec,
+) -> Result<(Pid, OwnedFd)> {
+    let (control_r, control_w) = socketpair(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        None,
+        SockFlag::empty(),
+    )
+    .context("Failed to create control socket pair")?;
+
+    let (executable, argv, envp, cwd) = prepare_exec(prelude_code, spec)?;
+
+    match unsafe { fork() }.context("fork failed")? {
+        ForkResult::Parent { child } => Ok((child, control_w)),
+        ForkResult::Child => {
+            if acquire_tty {
+                setsid().expect("setsid failed");
+            }
+
+            dup2(fds[0], 0).expect("dup2 stdin failed");
+            dup2(fds[1], 1).expect("dup2 stdout failed");
+            dup2(fds[2], 2).expect("dup2 stderr failed");
+            for fd in fds {
+                if fd > 2 {
+                    close(fd).expect("failed to close caller-supplied fd");
+                }
+            }
+
+            // Dup control_r fd to 3 so that it survives exec and can be used by interpreter
+            dup2(control_r.as_raw_fd(), 3).expect("dup2 control failed");
+
+            if acquire_tty {
+                unsafe { ioctl_set_ctty(0, 0) }.expect("ioctl(TIOCSCTTY) failed");
+                let pid = getpid();
+                tcsetpgrp(std::io::stdin(), pid).expect("tcsetpgrp failed");
+            }
+
+            exec_prepared(executable, argv, envp, cwd)
         }
     }
 }
@@ -1,14 +1,11 @@
 use anyhow::{bail, Context, Result};
-use std::net::Shutdown;
-use std::io::{BufRead, Write};
 use nix::fcntl::{open, OFlag};
-use std::io::BufReader;
+use nix::sys::socket::{recv, send, shutdown, MsgFlags, Shutdown};
 use std::os::fd::{FromRawFd, OwnedFd};
-use std::os::unix::net::UnixStream;
 use nix::libc;
 use nix::pty::{grantpt, posix_openpt, ptsname, unlockpt, PtyMaster};
 use nix::sys::stat::Mode;
-use std::fd::File;
+use std::fs::File;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 use nix::unistd::{close, dup2, execvp, fork, getpid, setsid, tcsetpgrp, ForkResult};
@@ -74,16 +71,17 @@ impl ChildId {
 
 pub struct Interpreter {
     id: ChildId,
-    control_fd: UnixStream,
+    // SOCK_SEQPACKET socket: each send()/recv() carries exactly one message, so the control
+    // protocol needs no newline framing or quoting of embedded newlines.
+    control_fd: OwnedFd,
     pty_master_fd: File,
     supervised: bool,
-    control_reader: BufReader<UnixStream>,
 }
 
 
 impl Interpreter {
-    pub fn new(id: ChildId, control_fd: UnixStream, pty_master_fd: File) -> Self {
-        Interpreter { id, control_fd, pty_master_fd, supervised: true, control_reader: BufReader::new(control_fd) }
+    pub fn new(id: ChildId, control_fd: OwnedFd, pty_master_fd: File) -> Self {
+        Interpreter { id, control_fd, pty_master_fd, supervised: true }
     }
 
     pub fn id(&self) -> &ChildId {
@@ -94,12 +92,22 @@ impl Interpreter {
         &self.pty_master_fd
     }
 
+    // Raw fd numbers for `control_fd`/`pty_master_fd`, without transferring ownership. Used
+    // when clearing FD_CLOEXEC ahead of a server re-exec, where these fds need to survive
+    // execve() in place rather than being handed to another process via SCM_RIGHTS.
+    pub fn raw_fds(&self) -> (RawFd, RawFd) {
+        (self.control_fd.as_raw_fd(), self.pty_master_fd.as_raw_fd())
+    }
+
     pub fn unsupervise(&mut self) -> Result<()> {
-        self.control_fd.write_all(format!("{:?}\n", PY_STOP_SUPERVISION.trim()).as_ref()).context("interpreter unsupervise send failed")?;
-        let mut response_buf = String::new();
-        self.control_reader.read_line(&mut response_buf).context("interpreter unsupervise read_line failed")?;
-        if response_buf.trim() != "OK" {
-            bail!("interpreter unsupervise error: {}", response_buf.trim())
+        send(self.control_fd.as_raw_fd(), PY_STOP_SUPERVISION.as_bytes(), MsgFlags::empty())
+            .context("interpreter unsupervise send failed")?;
+        let mut buf = [0u8; 256];
+        let n = recv(self.control_fd.as_raw_fd(), &mut buf, MsgFlags::empty())
+            .context("interpreter unsupervise recv failed")?;
+        let response = String::from_utf8_lossy(&buf[..n]);
+        if response.trim() != "OK" {
+            bail!("interpreter unsupervise error: {}", response.trim())
         }
         self.supervised = false;
         Ok(())
@@ -107,19 +115,18 @@ impl Interpreter {
 
     pub fn run_instructions(&mut self, instructions: &str) -> Result<()> {
         assert!(!self.supervised, "still supervised");
-        self.control_fd.write_all(format!("{:?}\n", instructions).as_ref()).context("interpreter run_instructions send failed")?;
-        self.control_fd.shutdown(Shutdown::Both).context("shutdown function failed")?;
+        send(self.control_fd.as_raw_fd(), instructions.as_bytes(), MsgFlags::empty())
+            .context("interpreter run_instructions send failed")?;
+        shutdown(self.control_fd.as_raw_fd(), Shutdown::Both).context("shutdown function failed")?;
         Ok(())
     }
 
     pub unsafe fn from_raw(msg: &[u8], fds: &[RawFd]) -> Result<Self> {
-        let control_fd = UnixStream::from_raw_fd(fds[0]);
         Ok(Interpreter {
             id: ChildId::from_str(&String::from_utf8_lossy(msg))?,
-            control_fd,
+            control_fd: OwnedFd::from_raw_fd(fds[0]),
             pty_master_fd: OwnedFd::from_raw_fd(fds[1]).into(),
             supervised: false,
-            control_reader: BufReader::new(control_fd),
         })
     }
 
@@ -1,5 +1,8 @@
 mod hsclient;
 mod hsserver;
+mod interpreter;
+mod remote;
+mod sendfd;
 
 fn main() {
     match hsclient::cli::main() {
@@ -0,0 +1,42 @@
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
+
+// This protocol only ever carries a short command (TAKE/EXITCODE), a shared-secret token, and a
+// handful of strings (executable/args/env/cwd/instructions) — nothing remotely close to this
+// size. Reject a claimed length beyond it before allocating, so a peer can't force a multi-GB
+// allocation with a single 4-byte length prefix before the connection is even authenticated.
+const MAX_JSON_FRAME_LEN: usize = 1 << 20;
+
+// Length-prefixed JSON framing for the remote-interpreter protocol (see `hsserver::remote` and
+// `hsclient::client`'s `*_remote` functions): a 4-byte big-endian payload length followed by
+// that many bytes of UTF-8 JSON. Unlike `sendfd::{read_frame, write_frame}`, no fds ride along
+// here — SCM_RIGHTS can't cross a TCP connection, which is the whole reason this separate,
+// fd-less protocol exists.
+pub fn write_json_frame<S: Write>(stream: &mut S, value: &json::JsonValue) -> Result<()> {
+    let bytes = json::stringify(value.clone()).into_bytes();
+    let len = u32::try_from(bytes.len()).context("frame payload too large")?;
+    stream
+        .write_all(&len.to_be_bytes())
+        .context("failed to write frame length")?;
+    stream
+        .write_all(&bytes)
+        .context("failed to write frame payload")?;
+    Ok(())
+}
+
+pub fn read_json_frame<S: Read>(stream: &mut S) -> Result<json::JsonValue> {
+    let mut len_buf = [0u8; 4];
+    stream
+        .read_exact(&mut len_buf)
+        .context("failed to read frame length")?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_JSON_FRAME_LEN {
+        bail!("frame length {} exceeds maximum of {} bytes", len, MAX_JSON_FRAME_LEN);
+    }
+
+    let mut payload = vec![0u8; len];
+    stream
+        .read_exact(&mut payload)
+        .context("failed to read frame payload")?;
+    json::parse(&String::from_utf8_lossy(&payload)).context("invalid JSON frame")
+}
@@ -1,10 +1,102 @@
+use anyhow::{bail, Context, Result};
 use nix::libc;
 use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
 use nix::sys::socket::{sendmsg, ControlMessage};
 use std::io;
+use std::io::{Read, Write};
 use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::os::unix::net;
 
+// Largest number of fds any single frame in this protocol ever carries (TAKE3's request: the
+// caller's stdin/stdout/stderr).
+const MAX_FRAME_FDS: usize = 3;
+
+/// Write `bytes` as one framed message: a 4-byte big-endian payload length, a 4-byte big-endian
+/// fd count, then exactly that many payload bytes, looping to cope with partial writes. `fds`
+/// (if any) ride along with the first chunk of the payload via `SendWithFd`; pass `&[]` when
+/// there's nothing to pass. The explicit fd count tells the reader how many fds to wait for.
+/// The fds ride on a payload chunk, so when `bytes` is empty they are not delivered to the peer
+/// (a zero-length `sendmsg` on a stream socket carries no ancillary data). Also copes with `stream`
+/// being non-blocking: an EAGAIN from the SCM_RIGHTS sendmsg is retried rather than failed.
+pub fn write_frame<S: SendWithFd + Write>(
+    stream: &mut S,
+    bytes: &[u8],
+    fds: &[RawFd],
+) -> Result<()> {
+    let len = u32::try_from(bytes.len()).context("frame payload too large")?;
+    let fd_count = u32::try_from(fds.len()).context("too many fds for one frame")?;
+    stream
+        .write_all(&len.to_be_bytes())
+        .context("failed to write frame length")?;
+    stream
+        .write_all(&fd_count.to_be_bytes())
+        .context("failed to write frame fd count")?;
+
+    let mut sent = 0;
+    let mut fds_sent = false;
+    loop {
+        let chunk_fds = if fds_sent { &[] } else { fds };
+        if sent >= bytes.len() && chunk_fds.is_empty() {
+            break;
+        }
+        // sendmsg returns EAGAIN/EWOULDBLOCK instead of blocking if the stream is non-blocking
+        // and the socket buffer is momentarily full; spin briefly rather than treating that as a
+        // hard failure; everything else (including a real disconnect) still propagates.
+        let n = loop {
+            match stream.send_with_fd(&bytes[sent..], chunk_fds) {
+                Ok(n) => break n,
+                Err(nix::errno::Errno::EAGAIN) => {
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                    continue;
+                }
+                Err(e) => return Err(e).context("failed to write frame payload"),
+            }
+        };
+        fds_sent = true;
+        if n == 0 && chunk_fds.is_empty() {
+            bail!("connection closed mid-frame");
+        }
+        sent += n;
+    }
+    Ok(())
+}
+
+/// Read one framed message written by `write_frame`: a 4-byte big-endian payload length, a
+/// 4-byte big-endian fd count, then exactly that many payload bytes, looping to cope with short
+/// reads. Keeps calling `recv_with_fd` until both the full payload and the full fd count
+/// (whichever arrives last, since the kernel may split them across `recvmsg` calls differently
+/// than `write_frame` chunked them) have been collected, so fds arriving on a later `recvmsg`
+/// than the last payload byte are still picked up.
+pub fn read_frame<S: RecvWithFd + Read>(stream: &mut S) -> Result<(Vec<u8>, Vec<RawFd>)> {
+    let mut len_buf = [0u8; 4];
+    stream
+        .read_exact(&mut len_buf)
+        .context("failed to read frame length")?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+
+    let mut fd_count_buf = [0u8; 4];
+    stream
+        .read_exact(&mut fd_count_buf)
+        .context("failed to read frame fd count")?;
+    let fd_count = u32::from_be_bytes(fd_count_buf) as usize;
+
+    let mut payload = vec![0u8; len];
+    let mut fds = Vec::new();
+    let mut read = 0;
+    while read < len || fds.len() < fd_count {
+        let mut fd_arr = [0 as RawFd; MAX_FRAME_FDS];
+        let (n, n_fds) = stream
+            .recv_with_fd(&mut payload[read..], &mut fd_arr)
+            .context("failed to read frame payload")?;
+        if n == 0 && n_fds == 0 {
+            bail!("connection closed mid-frame");
+        }
+        fds.extend_from_slice(&fd_arr[..n_fds]);
+        read += n;
+    }
+    Ok((payload, fds))
+}
+
 /// An extension trait that enables sending associated file descriptors along with the data.
 pub trait SendWithFd {
     /// Send the bytes and the file descriptors.